@@ -1,18 +1,57 @@
 use std::{
-	fs::{self, File},
+	fs::File,
 	io::BufWriter,
-	path::PathBuf,
+	path::{Path, PathBuf},
+	sync::{
+		atomic::{AtomicU32, Ordering},
+		Mutex,
+	},
 	thread,
-	time::Instant,
+	time::{Duration, Instant},
 };
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use indicatif::{ProgressBar, ProgressStyle};
-use pathtracer::{scene::Scene, Pathtracer};
+use pathtracer::{
+	checkpoint::{self, Checkpoint},
+	raw::RawImage,
+	scene::Scene,
+	Pathtracer, Region,
+};
 
 #[derive(Debug, Parser)]
-#[command(author, version, about)]
+#[command(author, version, about, args_conflicts_with_subcommands = true)]
 struct Args {
+	#[command(subcommand)]
+	command: Option<Command>,
+
+	#[command(flatten)]
+	render: Option<RenderArgs>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+	/// Combine raw partial renders of the same scene into one image
+	Merge(MergeArgs),
+}
+
+#[derive(Debug, clap::Args)]
+struct MergeArgs {
+	/// Raw partial renders to merge
+	#[arg(required = true, value_name = "FILES")]
+	inputs: Vec<PathBuf>,
+
+	/// Path to output file
+	#[arg(short, long, value_name = "FILE")]
+	output: PathBuf,
+
+	/// Also write the merged raw image, so that it can be merged again
+	#[arg(long, value_name = "FILE")]
+	raw: Option<PathBuf>,
+}
+
+#[derive(Debug, clap::Args)]
+struct RenderArgs {
 	/// Path to output file
 	#[arg(short, long, value_name = "FILE")]
 	output: PathBuf,
@@ -29,6 +68,11 @@ struct Args {
 	#[arg(long = "spp", default_value_t = 10)]
 	samples_per_pixel: u32,
 
+	/// Keep adding samples until this many seconds have passed, instead of
+	/// rendering a fixed number of samples per pixel
+	#[arg(long, value_name = "SECONDS", conflicts_with = "samples_per_pixel")]
+	time_limit: Option<f64>,
+
 	/// Maximum number of bounces for a single ray
 	#[arg(long = "bounces", default_value_t = 10)]
 	max_bounces: u32,
@@ -37,14 +81,89 @@ struct Args {
 	#[arg(long = "threads", default_value_t = 1)]
 	n_threads: u32,
 
-	/// Path to scene.toml
+	/// Path to a TOML, JSON, RON or YAML scene file, or a glTF file with a
+	/// camera
 	#[arg(short = 'i', long, value_name = "FILE")]
 	scene: PathBuf,
+
+	/// Directory to look in for files referenced by the scene, when they are
+	/// not found relative to the scene file. Can be given multiple times
+	#[arg(long = "search-path", value_name = "DIR")]
+	search_paths: Vec<PathBuf>,
+
+	/// Only render this part of the image, leaving the rest transparent
+	#[arg(long, value_name = "X,Y,WIDTH,HEIGHT")]
+	region: Option<Region>,
+
+	/// Periodically save the render progress to this file
+	#[arg(long, value_name = "FILE")]
+	checkpoint: Option<PathBuf>,
+
+	/// Number of seconds between saving checkpoints
+	#[arg(long, default_value_t = 60)]
+	checkpoint_interval: u64,
+
+	/// Continue rendering from the checkpoint file, until the total number of
+	/// samples per pixel is reached
+	#[arg(long, requires = "checkpoint")]
+	resume: bool,
+
+	/// Also write the unprocessed render, which can be merged with other
+	/// renders of the same scene
+	#[arg(long, value_name = "FILE")]
+	raw: Option<PathBuf>,
 }
 
 fn main() -> Result<(), ()> {
-	let args = Args::parse();
+	match Args::parse() {
+		Args {
+			command: Some(Command::Merge(args)),
+			..
+		} => merge(&args),
+		Args {
+			render: Some(args), ..
+		} => render_scene(&args),
+		_ => {
+			eprintln!("Error: missing scene and output file, see --help");
+			Err(())
+		}
+	}
+}
+
+fn merge(args: &MergeArgs) -> Result<(), ()> {
+	let mut images = Vec::new();
+	for path in &args.inputs {
+		match RawImage::load(path) {
+			Ok(image) => images.push(image),
+			Err(err) => {
+				eprintln!("Error while loading {}: {err}", path.display());
+				return Err(());
+			}
+		}
+	}
+
+	let merged = RawImage::merge(&images).map_err(|err| eprintln!("Error: {err}"))?;
+	println!(
+		"Merged {} renders into {} samples per pixel",
+		images.len(),
+		merged.samples
+	);
+
+	if let Some(path) = &args.raw {
+		save_raw(path, &merged);
+	}
+	save(
+		&args.output,
+		merged.width,
+		merged.height,
+		merged.to_canvas(),
+		merged.samples,
+	);
+
+	Ok(())
+}
 
+fn render_scene(args: &RenderArgs) -> Result<(), ()> {
 	if let Ok(max_threads) = thread::available_parallelism() {
 		if args.n_threads as usize > max_threads.get() {
 			println!(
@@ -54,100 +173,194 @@ fn main() -> Result<(), ()> {
 		}
 	}
 
-	if args.n_threads > args.samples_per_pixel {
+	if args.time_limit.is_none() && args.n_threads > args.samples_per_pixel {
 		println!(
 			"Warning: number of threads ({}) is larger than the number of samples per pixel ({})",
 			args.n_threads, args.samples_per_pixel
 		)
 	}
 
-	let scene = get_scene(&args).ok_or(())?;
-	let canvas = render(&args, scene);
-	save(&args, canvas);
+	if let Some(region) = args.region {
+		if region.x >= args.width || region.y >= args.height {
+			eprintln!(
+				"Error: region {region} lies outside of the {}x{} image",
+				args.width, args.height
+			);
+			return Err(());
+		}
+	}
+
+	let scene = get_scene(args).ok_or(())?;
+	let checkpoint = get_checkpoint(args, &scene).ok_or(())?;
+	let checkpoint = render(args, scene, checkpoint);
+	if let Some(path) = &args.checkpoint {
+		save_checkpoint(path, &checkpoint);
+	}
+	if let Some(path) = &args.raw {
+		save_raw(path, &RawImage::from_checkpoint(&checkpoint));
+	}
+	let canvas = draw(args, &checkpoint);
+	save(
+		&args.output,
+		args.width,
+		args.height,
+		canvas,
+		checkpoint.samples,
+	);
 
 	Ok(())
 }
 
-fn get_scene(args: &Args) -> Option<Scene> {
-	let scene_str = fs::read_to_string(args.scene.clone()).unwrap();
-	match toml::from_str(&scene_str) {
-		Ok(scene) => Some(scene),
+fn get_checkpoint(args: &RenderArgs, scene: &Scene) -> Option<Checkpoint> {
+	let scene_hash = checkpoint::scene_hash(scene);
+	let new = Checkpoint::new(
+		args.width,
+		args.height,
+		args.region,
+		args.max_bounces,
+		scene_hash,
+	);
+	let path = match &args.checkpoint {
+		Some(path) if args.resume => path,
+		_ => return Some(new),
+	};
+	if scene_hash.is_none() {
+		println!("Warning: cannot check that the checkpoint was rendered from the same scene");
+	}
+
+	match Checkpoint::load(path) {
+		Ok(checkpoint) => match checkpoint.check_resumable(&new) {
+			Ok(()) => {
+				println!("Resuming from {} samples per pixel", checkpoint.samples);
+				Some(checkpoint)
+			}
+			Err(err) => {
+				eprintln!("Error: {err}");
+				None
+			}
+		},
 		Err(err) => {
-			match err.span() {
-				Some(span) => {
-					let line = scene_str[..span.start]
-						.chars()
-						.filter(|&c| c == '\n')
-						.count() + 1;
-					eprintln!("Error in scene file at line {line}: {}", err.message())
-				}
-				None => eprintln!("Error in scene file: {}", err.message()),
-			};
+			eprintln!("Error while loading checkpoint: {err}");
 			None
 		}
 	}
 }
 
-fn render(args: &Args, scene: Scene) -> Vec<u8> {
-	let mut shared_pixels = vec![0; (args.width * args.height * 4) as usize];
+fn save_checkpoint(path: &Path, checkpoint: &Checkpoint) {
+	if let Err(err) = checkpoint.save(path) {
+		eprintln!("Error while saving checkpoint: {err}");
+	}
+}
 
-	let progress_bar = ProgressBar::new(args.samples_per_pixel as u64).with_style(
-		ProgressStyle::with_template("▕{wide_bar}▏{pos:>4}/{len:4} ETA {eta} ")
-			.unwrap()
-			.progress_chars("█▉▊▋▌▍▎▏ "),
-	);
+fn get_scene(args: &RenderArgs) -> Option<Scene> {
+	Scene::load_with_search_paths(&args.scene, args.search_paths.clone())
+		.map_err(|err| eprintln!("Error: {err}"))
+		.ok()
+}
+
+fn render(args: &RenderArgs, scene: Scene, checkpoint: Checkpoint) -> Checkpoint {
+	let progress_bar = match args.time_limit {
+		Some(_) => ProgressBar::new_spinner()
+			.with_style(ProgressStyle::with_template("{spinner} {pos:>4} spp {elapsed}").unwrap()),
+		None => ProgressBar::new(args.samples_per_pixel as u64).with_style(
+			ProgressStyle::with_template("▕{wide_bar}▏{pos:>4}/{len:4} ETA {eta} ")
+				.unwrap()
+				.progress_chars("█▉▊▋▌▍▎▏ "),
+		),
+	}
+	.with_position(checkpoint.samples as u64);
 	let start_time = Instant::now();
 
-	let mut samples_left = args.samples_per_pixel;
-	let samples_per_thread = (args.samples_per_pixel as f32 / args.n_threads as f32).ceil() as u32;
+	let seed = checkpoint.seed;
+	let first_pass = checkpoint.next_pass;
+	let (remaining, deadline) = match args.time_limit {
+		Some(seconds) => (
+			u32::MAX,
+			Some(start_time + Duration::from_secs_f64(seconds)),
+		),
+		None => (
+			args.samples_per_pixel.saturating_sub(checkpoint.samples),
+			None,
+		),
+	};
+	let end_pass = first_pass.saturating_add(remaining);
+	let next_pass = AtomicU32::new(first_pass);
+	let checkpoint_interval = Duration::from_secs(args.checkpoint_interval);
+	let shared = Mutex::new((checkpoint, Instant::now()));
 
 	thread::scope(|scope| {
-		let mut pathtracers = Vec::new();
-		for _i in 0..args.n_threads {
-			let n_samples = samples_per_thread.min(samples_left);
-			samples_left -= n_samples;
-			if n_samples <= 0 {
-				break;
-			}
+		for _i in 0..args.n_threads.min(remaining) {
 			let scene = scene.clone();
-			let progress_bar = progress_bar.clone();
-			pathtracers.push(scope.spawn(move || {
+			let (progress_bar, next_pass, shared) = (&progress_bar, &next_pass, &shared);
+			scope.spawn(move || {
 				let mut pathtracer =
 					Pathtracer::new(args.width, args.height, args.max_bounces, scene);
-				for _j in 0..n_samples {
-					pathtracer.render_single();
+				pathtracer.seed = seed;
+				if let Some(region) = args.region {
+					pathtracer.region = region;
+				}
+				loop {
+					let pass = next_pass.fetch_add(1, Ordering::Relaxed);
+					// Always render at least one pass, to have something to show
+					let out_of_time = deadline.is_some_and(|deadline| Instant::now() >= deadline)
+						&& pass != first_pass;
+					if pass >= end_pass || out_of_time {
+						break;
+					}
+					pathtracer.render_pass(pass);
+
+					let (checkpoint, last_save) = &mut *shared.lock().unwrap();
+					checkpoint.add(&pathtracer.pixels);
+					pathtracer.pixels.fill(0);
 					progress_bar.inc(1);
+
+					if let Some(path) = &args.checkpoint {
+						if last_save.elapsed() >= checkpoint_interval {
+							// Passes that are still being rendered by other
+							// threads are skipped when resuming.
+							checkpoint.next_pass = next_pass.load(Ordering::Relaxed).min(end_pass);
+							save_checkpoint(path, checkpoint);
+							*last_save = Instant::now();
+						}
+					}
 				}
-				pathtracer
-			}));
-		}
-		for handle in pathtracers.into_iter() {
-			for (idx, subpixel) in handle.join().unwrap().pixels.iter().enumerate() {
-				shared_pixels[idx] += subpixel;
-			}
+			});
 		}
 	});
 
 	let render_time = Instant::now();
-
-	let mut canvas: Vec<u8> = vec![0; (args.width * args.height * 4) as usize];
-	pathtracer::draw_pixels_to_canvas(&shared_pixels, &mut canvas, args.samples_per_pixel);
-
 	progress_bar.finish_with_message("done");
 	println!("render time: {:?}", render_time.duration_since(start_time));
 
+	let (mut checkpoint, _) = shared.into_inner().unwrap();
+	checkpoint.next_pass = next_pass.into_inner().min(end_pass);
+	checkpoint
+}
+
+fn draw(args: &RenderArgs, checkpoint: &Checkpoint) -> Vec<u8> {
+	let mut canvas: Vec<u8> = vec![0; (args.width * args.height * 4) as usize];
+	pathtracer::draw_pixels_to_canvas(&checkpoint.pixels, &mut canvas, checkpoint.samples);
+	if let Some(region) = args.region {
+		region.clear_outside(&mut canvas, args.width);
+	}
 	canvas
 }
 
-fn save(args: &Args, canvas: Vec<u8>) {
-	let file = File::create(args.output.clone()).unwrap();
-	let ref mut file_writer = BufWriter::new(file);
+fn save_raw(path: &Path, raw: &RawImage) {
+	if let Err(err) = raw.save(path) {
+		eprintln!("Error while saving raw image: {err}");
+	}
+}
+
+fn save(path: &Path, width: u32, height: u32, canvas: Vec<u8>, samples_per_pixel: u32) {
+	let file = File::create(path).unwrap();
+	let file_writer = &mut BufWriter::new(file);
 
-	let mut png_encoder = png::Encoder::new(file_writer, args.width, args.height);
+	let mut png_encoder = png::Encoder::new(file_writer, width, height);
 	png_encoder.set_color(png::ColorType::Rgba);
 	png_encoder.set_depth(png::BitDepth::Eight);
 	png_encoder
-		.add_text_chunk("spp".to_string(), args.samples_per_pixel.to_string())
+		.add_text_chunk("spp".to_string(), samples_per_pixel.to_string())
 		.unwrap();
 	let mut png_writer = png_encoder.write_header().unwrap();
 	png_writer.write_image_data(&canvas).unwrap();
@@ -1,18 +1,20 @@
 use core::time::Duration;
 use std::{
-	fs,
-	path::PathBuf,
+	path::{Path, PathBuf},
 	sync::{Arc, Mutex},
 	thread,
 };
 
 use clap::Parser;
-use euclid::default::Vector3D;
-use pathtracer::{scene::Scene, Pathtracer};
+use euclid::{
+	default::{Rotation3D, Vector3D},
+	Angle,
+};
+use pathtracer::{camera::Camera, scene::Scene, Pathtracer};
 use pixels::{Pixels, SurfaceTexture};
 use winit::{
-	dpi::PhysicalSize,
-	event::{ElementState, Event, VirtualKeyCode, WindowEvent},
+	dpi::{PhysicalPosition, PhysicalSize},
+	event::{ElementState, Event, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent},
 	event_loop::{ControlFlow, EventLoop},
 	window::{Fullscreen, Window, WindowBuilder},
 };
@@ -32,17 +34,115 @@ struct Args {
 	#[arg(long = "bounces", default_value_t = 10)]
 	max_bounces: u32,
 
-	/// Path to scene.toml
+	/// Path to a TOML, JSON, RON or YAML scene file, or a glTF file with a
+	/// camera
 	#[arg(short = 'i', long, value_name = "FILE")]
 	scene: PathBuf,
+
+	/// Directory to look in for files referenced by the scene, when they are
+	/// not found relative to the scene file. Can be given multiple times
+	#[arg(long = "search-path", value_name = "DIR")]
+	search_paths: Vec<PathBuf>,
+
+	/// File to save the scene to, with the current camera, when P is pressed.
+	/// The format depends on the extension, like for --scene
+	#[arg(long, value_name = "FILE")]
+	save: Option<PathBuf>,
+}
+
+/// Radians of rotation per pixel of mouse movement.
+const ROTATE_SPEED: f32 = 0.005;
+
+/// Fraction of the orbit distance moved per pixel of mouse movement.
+const PAN_SPEED: f32 = 0.002;
+
+/// Fraction of the orbit distance moved per line of scrolling.
+const ZOOM_SPEED: f32 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CameraMode {
+	/// Rotate around a point in front of the camera.
+	Orbit,
+	/// Rotate the camera in place.
+	Fly,
+}
+
+/// Clears the accumulated samples, to start rendering again after a change.
+fn reset(pt: &mut Pathtracer) {
+	pt.n_iterations = 0;
+	pt.pixels.fill(0);
 }
 
 fn move_cam(pt: &mut Pathtracer, by: Vector3D<f32>) {
 	let pos = pt.scene.camera.pos();
 	let dir = pt.scene.camera.dir();
 	pt.scene.camera.set_pos(pos + dir.component_mul(by));
-	pt.n_iterations = 0;
-	pt.pixels.fill(0);
+	reset(pt);
+}
+
+fn rotate(v: Vector3D<f32>, axis: Vector3D<f32>, angle: f32) -> Vector3D<f32> {
+	Rotation3D::around_axis(axis.normalize(), Angle::radians(angle)).transform_vector3d(v)
+}
+
+/// Rotates the direction of `camera` by `dx` and `dy` pixels of mouse
+/// movement, without tilting it past straight up or down.
+fn rotate_dir(camera: &Camera, dx: f32, dy: f32) -> Vector3D<f32> {
+	let up = camera.up();
+	let yaw = -dx * ROTATE_SPEED;
+	let dir = rotate(camera.dir(), up, yaw);
+	let right = rotate(camera.basis().0, up, yaw);
+	let pitched = rotate(dir, right, -dy * ROTATE_SPEED);
+	// Don't pitch over the top, but allow pitching away from it
+	let steepness = |dir: Vector3D<f32>| dir.normalize().dot(up).abs();
+	if steepness(pitched) < 0.99 || steepness(pitched) < steepness(dir) {
+		pitched
+	} else {
+		dir
+	}
+}
+
+/// Rotates the camera around the point `distance` in front of it.
+fn orbit_cam(pt: &mut Pathtracer, distance: f32, dx: f32, dy: f32) {
+	let camera = &mut pt.scene.camera;
+	let target = camera.pos() + camera.dir() * distance;
+	let dir = rotate_dir(camera, dx, dy);
+	camera.set_pos(target - dir * distance);
+	camera.set_dir(dir);
+	reset(pt);
+}
+
+/// Rotates the camera in place.
+fn look_cam(pt: &mut Pathtracer, dx: f32, dy: f32) {
+	let camera = &mut pt.scene.camera;
+	camera.set_dir(rotate_dir(camera, dx, dy));
+	reset(pt);
+}
+
+/// Moves the camera parallel to the image plane.
+fn pan_cam(pt: &mut Pathtracer, distance: f32, dx: f32, dy: f32) {
+	let camera = &mut pt.scene.camera;
+	let (right, up) = camera.basis();
+	let by = (right * -dx + up * dy) * (distance * PAN_SPEED);
+	camera.set_pos(camera.pos() + by);
+	reset(pt);
+}
+
+/// Moves the camera forward by `amount` lines of scrolling, keeping the orbit
+/// target in focus, and returns the new orbit distance.
+fn zoom_cam(pt: &mut Pathtracer, distance: f32, amount: f32) -> f32 {
+	let new_distance = (distance * (1.0 - amount * ZOOM_SPEED)).max(0.01);
+	let camera = &mut pt.scene.camera;
+	camera.set_pos(camera.pos() + camera.dir() * (distance - new_distance));
+	camera.set_focus_distance(new_distance);
+	reset(pt);
+	new_distance
+}
+
+fn save_scene(scene: &Scene, path: &Path) {
+	match scene.save(path) {
+		Ok(()) => println!("Saved scene to {}", path.display()),
+		Err(err) => eprintln!("Error: could not save scene to {}: {err}", path.display()),
+	}
 }
 
 fn create_pixels(args: &Args, window: &Window) -> Pixels {
@@ -64,20 +164,10 @@ fn main() {
 		.build(&event_loop)
 		.unwrap();
 
-	let scene_str = fs::read_to_string(args.scene.clone()).unwrap();
-	let scene: Scene = match toml::from_str(&scene_str) {
+	let scene = match Scene::load_with_search_paths(&args.scene, args.search_paths.clone()) {
 		Ok(scene) => scene,
 		Err(err) => {
-			match err.span() {
-				Some(span) => {
-					let line = scene_str[..span.start]
-						.chars()
-						.filter(|&c| c == '\n')
-						.count() + 1;
-					eprintln!("Error in scene file at line {line}: {}", err.message())
-				}
-				None => eprintln!("Error in scene file: {}", err.message()),
-			};
+			eprintln!("Error: {err}");
 			return;
 		}
 	};
@@ -88,6 +178,11 @@ fn main() {
 	};
 	let pixels = Arc::new(Mutex::new(create_pixels(&args, &window)));
 
+	let mut camera_mode = CameraMode::Orbit;
+	let mut orbit_distance = scene.camera.focus_distance();
+	let mut drag_button = None;
+	let mut cursor_pos = PhysicalPosition::new(0.0, 0.0);
+
 	{
 		let pathtracer = Arc::clone(&pathtracer);
 		let pixels = Arc::clone(&pixels);
@@ -112,9 +207,44 @@ fn main() {
 				// prevent deadlocks.
 				let mut pt = pathtracer.lock().unwrap();
 				let mut pixels = pixels.lock().unwrap();
-				*pt = Pathtracer::new(args.width, args.height, args.max_bounces, scene.clone());
+				let scene = pt.scene.clone();
+				*pt = Pathtracer::new(args.width, args.height, args.max_bounces, scene);
 				*pixels = create_pixels(&args, &window);
 			}
+			WindowEvent::MouseInput { state, button, .. } => {
+				drag_button = match state {
+					ElementState::Pressed => Some(button),
+					ElementState::Released => None,
+				};
+			}
+			WindowEvent::CursorMoved { position, .. } => {
+				let dx = (position.x - cursor_pos.x) as f32;
+				let dy = (position.y - cursor_pos.y) as f32;
+				cursor_pos = position;
+				match (drag_button, camera_mode) {
+					(Some(MouseButton::Left), CameraMode::Orbit) => {
+						let pt = &mut pathtracer.lock().unwrap();
+						orbit_cam(pt, orbit_distance, dx, dy)
+					}
+					(Some(MouseButton::Left), CameraMode::Fly) => {
+						let pt = &mut pathtracer.lock().unwrap();
+						look_cam(pt, dx, dy)
+					}
+					(Some(MouseButton::Right | MouseButton::Middle), _) => {
+						let pt = &mut pathtracer.lock().unwrap();
+						pan_cam(pt, orbit_distance, dx, dy)
+					}
+					_ => (),
+				}
+			}
+			WindowEvent::MouseWheel { delta, .. } => {
+				let amount = match delta {
+					MouseScrollDelta::LineDelta(_, y) => y,
+					MouseScrollDelta::PixelDelta(pos) => pos.y as f32 / 20.0,
+				};
+				let pt = &mut pathtracer.lock().unwrap();
+				orbit_distance = zoom_cam(pt, orbit_distance, amount);
+			}
 			WindowEvent::KeyboardInput { input, .. } => {
 				if input.state == ElementState::Released {
 					return;
@@ -142,11 +272,21 @@ fn main() {
 					}
 					Some(VirtualKeyCode::R) => {
 						let pt = &mut pathtracer.lock().unwrap();
-						pt.n_iterations = 0;
-						pt.pixels.fill(0);
+						reset(pt);
 					}
+					Some(VirtualKeyCode::C) => {
+						camera_mode = match camera_mode {
+							CameraMode::Orbit => CameraMode::Fly,
+							CameraMode::Fly => CameraMode::Orbit,
+						};
+						println!("Camera mode: {camera_mode:?}");
+					}
+					Some(VirtualKeyCode::P) => match &args.save {
+						Some(path) => save_scene(&pathtracer.lock().unwrap().scene, path),
+						None => println!("Use --save to choose where to save the scene"),
+					},
 					Some(VirtualKeyCode::F11) => {
-						if window.fullscreen() != None {
+						if window.fullscreen().is_some() {
 							window.set_fullscreen(None);
 						} else {
 							window.set_fullscreen(Some(Fullscreen::Borderless(None)));
@@ -158,11 +298,9 @@ fn main() {
 					_ => (),
 				}
 			}
-			WindowEvent::ModifiersChanged(state) => {
-				if state.shift() {
-					let pt = &mut pathtracer.lock().unwrap();
-					move_cam(pt, Vector3D::new(0.0, 0.0, -0.2))
-				}
+			WindowEvent::ModifiersChanged(state) if state.shift() => {
+				let pt = &mut pathtracer.lock().unwrap();
+				move_cam(pt, Vector3D::new(0.0, 0.0, -0.2))
 			}
 			_ => (),
 		},
@@ -0,0 +1,140 @@
+use std::{
+	cell::RefCell,
+	mem,
+	path::{self, Component, Path, PathBuf},
+	thread::LocalKey,
+};
+
+thread_local! {
+	static CURRENT: RefCell<AssetPaths> = RefCell::new(AssetPaths::default());
+	static SAVE_DIR: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+}
+
+/// Where to look for files (like textures) that are referenced by a scene
+/// file.
+#[derive(Debug, Clone, Default)]
+pub struct AssetPaths {
+	/// The directory containing the scene file. Relative paths are resolved
+	/// against the current working directory if this is [`None`].
+	pub base_dir: Option<PathBuf>,
+
+	/// Directories to look in when a file is not found in `base_dir`.
+	pub search_paths: Vec<PathBuf>,
+}
+
+impl AssetPaths {
+	pub fn new(base_dir: Option<PathBuf>, search_paths: Vec<PathBuf>) -> Self {
+		Self {
+			base_dir,
+			search_paths,
+		}
+	}
+
+	/// Returns the path to the file at the relative `path`, looking in the base
+	/// directory first and then in the search paths. If the file does not
+	/// exist anywhere, the path relative to the base directory is returned.
+	pub fn resolve(&self, path: &Path) -> PathBuf {
+		let in_base_dir = match &self.base_dir {
+			Some(base_dir) => base_dir.join(path),
+			None => path.to_path_buf(),
+		};
+		if path.is_absolute() || in_base_dir.exists() {
+			return in_base_dir;
+		}
+
+		self.search_paths
+			.iter()
+			.map(|dir| dir.join(path))
+			.find(|path| path.exists())
+			.unwrap_or(in_base_dir)
+	}
+
+	/// Runs `f` with these asset paths in use by [`resolve`], restoring the
+	/// previous ones afterwards.
+	pub(crate) fn scope<T>(&self, f: impl FnOnce() -> T) -> T {
+		let _restore = Restore::replace(&CURRENT, self.clone());
+		f()
+	}
+}
+
+/// Puts back the previous value of a thread-local when dropped, also when
+/// loading or saving panics.
+pub(crate) struct Restore<T: Default + 'static>(&'static LocalKey<RefCell<T>>, T);
+
+impl<T: Default> Restore<T> {
+	pub(crate) fn replace(key: &'static LocalKey<RefCell<T>>, value: T) -> Self {
+		Self(key, key.with(|current| current.replace(value)))
+	}
+}
+
+impl<T: Default> Drop for Restore<T> {
+	fn drop(&mut self) {
+		self.0
+			.with(|current| current.replace(mem::take(&mut self.1)));
+	}
+}
+
+/// Resolves `path` using the asset paths of the scene file that is currently
+/// being loaded. Used while deserializing, where there is no other way to pass
+/// the location of the scene file along.
+pub(crate) fn resolve(path: &Path) -> PathBuf {
+	CURRENT.with(|current| current.borrow().resolve(path))
+}
+
+/// Returns the asset paths of the scene file that is currently being loaded.
+pub(crate) fn current() -> AssetPaths {
+	CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Runs `f` with [`saved_path`] writing paths relative to `dir`, the
+/// directory of the scene file that is being saved.
+pub(crate) fn save_scope<T>(dir: &Path, f: impl FnOnce() -> T) -> T {
+	let _restore = Restore::replace(&SAVE_DIR, Some(dir.to_path_buf()));
+	f()
+}
+
+/// Returns how `path` is written in a scene file that is being saved: relative
+/// to the directory of that file if they share a parent directory, and
+/// absolute otherwise.
+pub(crate) fn saved_path(path: &Path) -> PathBuf {
+	let Some(path) = absolute(path) else {
+		return path.to_path_buf();
+	};
+	let dir = SAVE_DIR.with(|dir| dir.borrow().clone());
+	match dir.as_deref().and_then(absolute) {
+		Some(dir) => relative_to(&path, &dir).unwrap_or(path),
+		None => path,
+	}
+}
+
+/// Makes `path` absolute and removes `.` and `..` from it.
+fn absolute(path: &Path) -> Option<PathBuf> {
+	let path = path::absolute(path).ok()?;
+	let mut components = Vec::new();
+	for component in path.components() {
+		match component {
+			Component::CurDir => {}
+			Component::ParentDir if matches!(components.last(), Some(Component::Normal(_))) => {
+				components.pop();
+			}
+			component => components.push(component),
+		}
+	}
+	Some(components.iter().collect())
+}
+
+/// Returns the absolute `path` relative to the absolute `dir`, or [`None`] if
+/// they have no directory in common.
+fn relative_to(path: &Path, dir: &Path) -> Option<PathBuf> {
+	let path: Vec<_> = path.components().collect();
+	let dir: Vec<_> = dir.components().collect();
+	let common = path.iter().zip(&dir).take_while(|(a, b)| a == b).count();
+	if !path[..common]
+		.iter()
+		.any(|c| matches!(c, Component::Normal(_)))
+	{
+		return None;
+	}
+	let up = dir[common..].iter().map(|_| Component::ParentDir);
+	Some(up.chain(path[common..].iter().copied()).collect())
+}
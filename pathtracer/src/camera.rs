@@ -1,19 +1,43 @@
-use euclid::default::{Point3D, Vector3D};
-use serde::Deserialize;
+use core::f32::consts::PI;
 
-use crate::{ray::Ray, util};
+use euclid::default::{Point3D, Vector2D, Vector3D};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Deserialize)]
-#[serde(from = "SerializedCamera")]
+use crate::{
+	ray::Ray,
+	texture::{ImageTexture, Texture},
+	transform::Transform,
+	util,
+};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(try_from = "SerializedCamera", into = "SerializedCamera")]
 pub struct Camera {
 	pos: Point3D<f32>,
 	dir: Vector3D<f32>,
+	up: Vector3D<f32>,
+	roll: f32,
 	u: Vector3D<f32>,
 	v: Vector3D<f32>,
 	horizontal: Vector3D<f32>,
 	vertical: Vector3D<f32>,
 	lower_left_corner: Point3D<f32>,
+	aspect_ratio: f32,
+	fixed_aspect_ratio: Option<f32>,
+	fov: f32,
 	aperture: f32,
+	aperture_shape: ApertureShape,
+	focus_distance: f32,
+	projection: Projection,
+	shutter_open: f32,
+	shutter_close: f32,
+	exposure: f32,
+	physical: Option<PhysicalCamera>,
+
+	/// The point and exposure the camera was created with, which are saved as
+	/// they were given rather than recomputed, until the camera is changed.
+	look_at: Option<Point3D<f32>>,
+	explicit_exposure: Option<f32>,
 }
 
 impl Camera {
@@ -24,25 +48,34 @@ impl Camera {
 		fov: f32,
 		aperture: f32,
 		focus_distance: f32,
+		projection: Projection,
 	) -> Self {
-		let h = (fov.to_radians() / 2.0).tan();
-		let u = dir.cross(Vector3D::new(0.0, 0.0, 1.0));
-		let v = u.cross(dir);
-
-		let horizontal = u * (2.0 * h * focus_distance * aspect_ratio);
-		let vertical = v * (2.0 * h * focus_distance);
-		let lower_left_corner = pos - horizontal / 2.0 - vertical / 2.0 + dir * focus_distance;
-
-		Self {
+		let mut camera = Self {
 			pos,
-			dir,
-			u,
-			v,
-			horizontal,
-			vertical,
-			lower_left_corner,
+			dir: dir.normalize(),
+			up: Vector3D::new(0.0, 0.0, 1.0),
+			roll: 0.0,
+			u: Vector3D::zero(),
+			v: Vector3D::zero(),
+			horizontal: Vector3D::zero(),
+			vertical: Vector3D::zero(),
+			lower_left_corner: pos,
+			aspect_ratio,
+			fixed_aspect_ratio: None,
+			fov,
 			aperture,
-		}
+			aperture_shape: ApertureShape::Circle,
+			focus_distance,
+			projection,
+			shutter_open: 0.0,
+			shutter_close: 0.0,
+			exposure: 1.0,
+			physical: None,
+			look_at: None,
+			explicit_exposure: None,
+		};
+		camera.update();
+		camera
 	}
 
 	pub fn from_look_at(
@@ -51,24 +84,120 @@ impl Camera {
 		aspect_ratio: f32,
 		fov: f32,
 		aperture: f32,
+		projection: Projection,
 	) -> Self {
-		Self::new(
+		let mut camera = Self::new(
 			pos,
 			(look_at - pos).normalize(),
 			aspect_ratio,
 			fov,
 			aperture,
 			pos.distance_to(look_at),
-		)
+			projection,
+		);
+		camera.look_at = Some(look_at);
+		camera
+	}
+
+	/// Returns a ray through the point (`u`, `v`) on the image, where both
+	/// coordinates range from `0.0` to `1.0`. Returns [`None`] if the point
+	/// falls outside of the area covered by the projection.
+	pub fn get_ray(&self, u: f32, v: f32) -> Option<Ray> {
+		let time = util::random_in_range(self.shutter_open, self.shutter_close);
+
+		match self.projection {
+			Projection::Perspective => {
+				let rd = self.aperture_shape.sample() * (self.aperture / 2.0);
+				let offset = self.u * rd.x + self.v * rd.y;
+				Some(Ray::new(
+					self.pos + offset,
+					self.lower_left_corner + self.horizontal * u + self.vertical * v
+						- self.pos - offset,
+					time,
+				))
+			}
+			Projection::Orthographic { .. } => Some(Ray::new(
+				self.lower_left_corner + self.horizontal * u + self.vertical * v,
+				self.dir,
+				time,
+			)),
+			Projection::Equirectangular => {
+				// Keep the horizon level, regardless of the camera pitch and roll
+				let (right, _) = basis(self.dir, self.up);
+				let forward = self.up.cross(right);
+				let longitude = (u - 0.5) * 2.0 * PI;
+				let latitude = (v - 0.5) * PI;
+				let dir = forward * (latitude.cos() * longitude.cos())
+					+ right * (latitude.cos() * longitude.sin())
+					+ self.up * latitude.sin();
+				Some(Ray::new(self.pos, dir, time))
+			}
+			Projection::Fisheye => {
+				let x = (2.0 * u - 1.0) * self.aspect_ratio;
+				let y = 2.0 * v - 1.0;
+				let r = (x * x + y * y).sqrt();
+				if r > 1.0 {
+					return None;
+				}
+				let theta = r * self.fov.to_radians() / 2.0;
+				let sideways = if r > 0.0 {
+					(self.u * x + self.v * y) / r
+				} else {
+					Vector3D::zero()
+				};
+				let dir = self.dir * theta.cos() + sideways * theta.sin();
+				Some(Ray::new(self.pos, dir, time))
+			}
+		}
+	}
+
+	/// Sets the direction that is up in the world, and the rotation of the
+	/// camera around its viewing direction in degrees.
+	pub fn set_up(&mut self, up: Vector3D<f32>, roll: f32) {
+		self.up = up.normalize();
+		self.roll = roll;
+		self.update();
+	}
+
+	/// Sets the shape of the aperture, which determines the shape of
+	/// out-of-focus highlights.
+	pub fn set_aperture_shape(&mut self, shape: ApertureShape) {
+		self.aperture_shape = shape;
 	}
 
-	pub fn get_ray(&self, u: f32, v: f32) -> Ray {
-		let rd = util::random_in_unit_disc() * (self.aperture / 2.0);
-		let offset = self.u * rd.x + self.v * rd.y;
-		Ray::new(
-			self.pos + offset,
-			self.lower_left_corner + self.horizontal * u + self.vertical * v - self.pos - offset,
-		)
+	/// Sets the interval during which the shutter is open. Every ray is cast
+	/// at a random moment within this interval, which causes motion blur for
+	/// moving objects.
+	pub fn set_shutter(&mut self, open: f32, close: f32) {
+		self.shutter_open = open;
+		self.shutter_close = close;
+	}
+
+	/// Derives the field of view, aperture and exposure from the settings of a
+	/// physical camera.
+	pub fn set_physical(&mut self, physical: &PhysicalCamera) {
+		self.fov = physical.fov();
+		self.aperture = physical.aperture();
+		self.exposure = physical.exposure();
+		self.physical = Some(*physical);
+		self.update();
+	}
+
+	/// The settings of the physical camera, unless the field of view or
+	/// aperture has been changed since they were set.
+	pub fn physical(&self) -> Option<&PhysicalCamera> {
+		self.physical.as_ref()
+	}
+
+	/// The factor by which the incoming light is multiplied.
+	pub fn exposure(&self) -> f32 {
+		self.exposure
+	}
+
+	/// Sets the exposure, which is overridden by a physical camera.
+	pub fn set_exposure(&mut self, exposure: f32) {
+		self.exposure = exposure;
+		self.explicit_exposure = Some(exposure);
 	}
 
 	pub fn pos(&self) -> Point3D<f32> {
@@ -77,31 +206,452 @@ impl Camera {
 
 	pub fn set_pos(&mut self, pos: Point3D<f32>) {
 		self.pos = pos;
-		self.lower_left_corner = self.pos - self.horizontal / 2.0 - self.vertical / 2.0 + self.dir;
+		self.look_at = None;
+		self.update();
 	}
 
 	pub fn dir(&self) -> Vector3D<f32> {
 		self.dir
 	}
+
+	pub fn set_dir(&mut self, dir: Vector3D<f32>) {
+		self.dir = dir.normalize();
+		self.look_at = None;
+		self.update();
+	}
+
+	/// The point in focus straight ahead of the camera.
+	pub fn look_at(&self) -> Point3D<f32> {
+		self.look_at
+			.unwrap_or(self.pos + self.dir * self.focus_distance)
+	}
+
+	/// The direction that is up in the world.
+	pub fn up(&self) -> Vector3D<f32> {
+		self.up
+	}
+
+	/// The right and up directions of the image, including the roll. These
+	/// are well defined even when looking straight along the world up axis.
+	pub fn basis(&self) -> (Vector3D<f32>, Vector3D<f32>) {
+		(self.u, self.v)
+	}
+
+	pub fn aspect_ratio(&self) -> f32 {
+		self.aspect_ratio
+	}
+
+	/// Sets the aspect ratio to match an image of the given resolution, unless
+	/// the camera has a fixed aspect ratio.
+	pub fn set_resolution(&mut self, width: u32, height: u32) {
+		self.aspect_ratio = self
+			.fixed_aspect_ratio
+			.unwrap_or(width as f32 / height as f32);
+		self.update();
+	}
+
+	/// Sets an aspect ratio that is used regardless of the image resolution,
+	/// or [`None`] to derive it from the resolution.
+	pub fn set_fixed_aspect_ratio(&mut self, aspect_ratio: Option<f32>) {
+		self.fixed_aspect_ratio = aspect_ratio;
+		if let Some(aspect_ratio) = aspect_ratio {
+			self.aspect_ratio = aspect_ratio;
+			self.update();
+		}
+	}
+
+	/// The vertical field of view, in degrees.
+	pub fn fov(&self) -> f32 {
+		self.fov
+	}
+
+	pub fn set_fov(&mut self, fov: f32) {
+		self.fov = fov;
+		self.physical = None;
+		self.update();
+	}
+
+	pub fn aperture(&self) -> f32 {
+		self.aperture
+	}
+
+	pub fn set_aperture(&mut self, aperture: f32) {
+		self.aperture = aperture;
+		self.physical = None;
+	}
+
+	/// The distance from the camera to the plane that is in focus.
+	pub fn focus_distance(&self) -> f32 {
+		self.focus_distance
+	}
+
+	pub fn set_focus_distance(&mut self, focus_distance: f32) {
+		self.focus_distance = focus_distance;
+		self.look_at = None;
+		self.update();
+	}
+
+	/// Recomputes the camera basis and view frustum from its parameters.
+	fn update(&mut self) {
+		let (u, v) = basis(self.dir, self.up);
+		let (sin_roll, cos_roll) = self.roll.to_radians().sin_cos();
+		self.u = u * cos_roll + v * sin_roll;
+		self.v = v * cos_roll - u * sin_roll;
+
+		(self.horizontal, self.vertical) = match self.projection {
+			Projection::Perspective => {
+				let h = (self.fov.to_radians() / 2.0).tan();
+				(
+					self.u * (2.0 * h * self.focus_distance * self.aspect_ratio),
+					self.v * (2.0 * h * self.focus_distance),
+				)
+			}
+			Projection::Orthographic { height } => {
+				(self.u * (height * self.aspect_ratio), self.v * height)
+			}
+			Projection::Equirectangular | Projection::Fisheye => {
+				(Vector3D::zero(), Vector3D::zero())
+			}
+		};
+
+		self.lower_left_corner = self.pos - self.horizontal / 2.0 - self.vertical / 2.0;
+		if let Projection::Perspective = self.projection {
+			self.lower_left_corner += self.dir * self.focus_distance;
+		}
+	}
+}
+
+/// Returns the right and up vectors of a camera looking in the direction
+/// `dir`, such that they form an orthonormal basis together with `dir`. Falls
+/// back to an arbitrary basis when looking straight along the `up` axis.
+fn basis(dir: Vector3D<f32>, up: Vector3D<f32>) -> (Vector3D<f32>, Vector3D<f32>) {
+	let right = dir.cross(up);
+	let right = if right.square_length() > 1e-8 {
+		right.normalize()
+	} else {
+		util::orthonormal_basis(dir).0
+	};
+	(right, right.cross(dir))
+}
+
+/// How the camera maps the scene onto the image.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(tag = "type")]
+pub enum Projection {
+	/// Regular projection with perspective, like a physical camera.
+	#[default]
+	Perspective,
+
+	/// Parallel projection without perspective, where objects appear the same
+	/// size regardless of their distance to the camera.
+	Orthographic {
+		/// The height of the visible area, in scene units.
+		height: f32,
+	},
+
+	/// 360° panoramic projection, mapping longitude and latitude linearly
+	/// onto the horizontal and vertical axes of the image.
+	Equirectangular,
+
+	/// Circular fisheye projection covering the camera's field of view. The
+	/// image circle fits the height of the image.
+	Fisheye,
+}
+
+/// The shape of the camera aperture, which shows up in out-of-focus
+/// highlights (bokeh).
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(tag = "type")]
+pub enum ApertureShape {
+	/// A perfectly round aperture.
+	#[default]
+	Circle,
+
+	/// A regular polygon, like the aperture formed by the blades of a lens.
+	Polygon {
+		/// The number of aperture blades (the number of sides of the polygon).
+		blades: u32,
+
+		/// The rotation of the polygon, in degrees.
+		#[serde(default)]
+		rotation: f32,
+	},
+
+	/// An arbitrary shape given by an image, where brighter parts let through
+	/// more light. The image is stretched over the aperture's bounding square,
+	/// and the parts outside of its inscribed circle are cut off.
+	Image(ApertureMask),
+}
+
+impl ApertureShape {
+	/// Returns a random point on the aperture, within the unit disc.
+	fn sample(&self) -> Vector3D<f32> {
+		match self {
+			ApertureShape::Circle => util::random_in_unit_disc(),
+			ApertureShape::Polygon { blades, rotation } => {
+				// Pick a random triangle between the centre and one of the
+				// sides, and a uniformly distributed point within it.
+				let blades = (*blades).max(3);
+				let side = util::random::<u32>() % blades;
+				let angle = |i: u32| rotation.to_radians() + 2.0 * PI * i as f32 / blades as f32;
+				let a = Vector3D::new(angle(side).cos(), angle(side).sin(), 0.0);
+				let b = Vector3D::new(angle(side + 1).cos(), angle(side + 1).sin(), 0.0);
+				let r = util::random::<f32>().sqrt();
+				let t = util::random::<f32>();
+				a * (r * (1.0 - t)) + b * (r * t)
+			}
+			ApertureShape::Image(mask) => loop {
+				// Rejection sampling, relative to the brightest part so that
+				// dim masks don't take much longer
+				let p = util::random_in_unit_disc();
+				if util::random::<f32>() * mask.max_brightness < mask.brightness(p.x, p.y) {
+					return p;
+				}
+			},
+		}
+	}
+}
+
+/// An image used as the aperture shape, which must have some bright parts
+/// within the unit disc for light to pass through.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(try_from = "ImageTexture", into = "ImageTexture")]
+pub struct ApertureMask {
+	texture: ImageTexture,
+	max_brightness: f32,
+}
+
+impl ApertureMask {
+	/// The brightness at a point in the [-1, 1] square.
+	fn brightness(&self, x: f32, y: f32) -> f32 {
+		let uv = Vector2D::new((x + 1.0) / 2.0, (y + 1.0) / 2.0);
+		let colour = self.texture.colour(uv, Point3D::origin());
+		(colour.x + colour.y + colour.z) / 3.0
+	}
+}
+
+impl TryFrom<ImageTexture> for ApertureMask {
+	type Error = String;
+
+	fn try_from(texture: ImageTexture) -> Result<Self, Self::Error> {
+		let mut mask = Self {
+			max_brightness: 0.0,
+			texture,
+		};
+		// Look at the centre of every texel within the unit disc
+		let (width, height) = (mask.texture.width(), mask.texture.height());
+		for i in 0..width {
+			for j in 0..height {
+				let x = (2 * i + 1) as f32 / width as f32 - 1.0;
+				let y = (2 * j + 1) as f32 / height as f32 - 1.0;
+				if x * x + y * y < 1.0 {
+					mask.max_brightness = mask.max_brightness.max(mask.brightness(x, y));
+				}
+			}
+		}
+		if mask.max_brightness > 0.0 {
+			Ok(mask)
+		} else {
+			Err("the aperture image is black within its inscribed circle, so no light could pass through".to_string())
+		}
+	}
+}
+
+impl From<ApertureMask> for ImageTexture {
+	fn from(value: ApertureMask) -> Self {
+		value.texture
+	}
+}
+
+/// The settings of a real-world camera. Scene units are taken to be metres,
+/// and emission values to be luminance in cd/m².
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct PhysicalCamera {
+	/// The focal length of the lens, in millimetres.
+	pub focal_length: f32,
+
+	/// The height of the sensor, in millimetres. Defaults to that of a 35 mm
+	/// full-frame sensor.
+	#[serde(default = "PhysicalCamera::default_sensor_height")]
+	pub sensor_height: f32,
+
+	/// The f-number (f-stop) of the lens.
+	pub f_number: f32,
+
+	/// The shutter speed, in seconds.
+	pub shutter_speed: f32,
+
+	/// The sensor sensitivity.
+	pub iso: f32,
 }
 
-#[derive(Debug, Deserialize)]
-struct SerializedCamera {
+impl PhysicalCamera {
+	fn default_sensor_height() -> f32 {
+		24.0
+	}
+
+	/// Checks that all settings are positive, as none of the derived values
+	/// make sense otherwise.
+	fn validate(&self) -> Result<(), String> {
+		let settings = [
+			("focal_length", self.focal_length),
+			("sensor_height", self.sensor_height),
+			("f_number", self.f_number),
+			("shutter_speed", self.shutter_speed),
+			("iso", self.iso),
+		];
+		for (name, value) in settings {
+			if !(value.is_finite() && value > 0.0) {
+				return Err(format!("`{name}` is {value}, but must be positive"));
+			}
+		}
+		Ok(())
+	}
+
+	/// The vertical field of view in degrees.
+	pub fn fov(&self) -> f32 {
+		(2.0 * (self.sensor_height / (2.0 * self.focal_length)).atan()).to_degrees()
+	}
+
+	/// The diameter of the aperture, in metres.
+	pub fn aperture(&self) -> f32 {
+		self.focal_length / self.f_number / 1000.0
+	}
+
+	/// The factor to multiply luminance by to get a correctly exposed image.
+	// https://seblagarde.files.wordpress.com/2015/07/course_notes_moving_frostbite_to_pbr_v32.pdf
+	pub fn exposure(&self) -> f32 {
+		let ev100 = (self.f_number * self.f_number / self.shutter_speed * 100.0 / self.iso).log2();
+		1.0 / (1.2 * 2.0_f32.powf(ev100))
+	}
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct SerializedCamera {
 	pub pos: Point3D<f32>,
 	pub look_at: Point3D<f32>,
-	pub aspect_ratio: f32,
-	pub fov: f32,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub up: Option<Vector3D<f32>>,
+	#[serde(default)]
+	pub roll: f32,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub aspect_ratio: Option<f32>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub fov: Option<f32>,
+	#[serde(default)]
 	pub aperture: f32,
+	#[serde(default)]
+	pub aperture_shape: ApertureShape,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub physical: Option<PhysicalCamera>,
+
+	/// The factor by which the incoming light is multiplied, unless it is
+	/// derived from `physical`.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub exposure: Option<f32>,
+	#[serde(default)]
+	pub projection: Projection,
+	#[serde(default)]
+	pub shutter_open: f32,
+	#[serde(default)]
+	pub shutter_close: f32,
+
+	/// The transforms of the scenes this camera was included through, which
+	/// still have to be applied to the default up direction.
+	#[serde(skip)]
+	pub up_transform: Transform,
+}
+
+impl SerializedCamera {
+	/// Moves and rotates the camera. Scaling moves the camera but does not
+	/// change its field of view.
+	pub(crate) fn transform(&mut self, transform: &Transform) {
+		self.pos = transform.point(self.pos);
+		self.look_at = transform.point(self.look_at);
+		self.up = self.up.map(|up| transform.vector(up));
+		self.up_transform = self.up_transform.then(transform);
+	}
+
+	/// Builds the camera, using `default_up` as the up direction if the camera
+	/// does not specify one.
+	pub(crate) fn into_camera(self, default_up: Vector3D<f32>) -> Result<Camera, String> {
+		let fov = match (self.fov, &self.physical) {
+			(Some(fov), None) => fov,
+			(None, Some(physical)) => {
+				if self.aperture != 0.0 {
+					return Err(
+						"a physical camera derives its `aperture` from `f_number`".to_string()
+					);
+				}
+				physical.validate()?;
+				physical.fov()
+			}
+			(Some(_), Some(_)) => {
+				return Err("camera cannot have both `fov` and `physical`".to_string())
+			}
+			(None, None) => return Err("camera needs either `fov` or `physical`".to_string()),
+		};
+		let mut camera = Camera::from_look_at(
+			self.pos,
+			self.look_at,
+			1.0,
+			fov,
+			self.aperture,
+			self.projection,
+		);
+		let up = self
+			.up
+			.unwrap_or_else(|| self.up_transform.vector(default_up));
+		camera.set_up(up, self.roll);
+		camera.set_fixed_aspect_ratio(self.aspect_ratio);
+		camera.set_shutter(self.shutter_open, self.shutter_close);
+		camera.set_aperture_shape(self.aperture_shape);
+		if let Some(exposure) = self.exposure {
+			camera.set_exposure(exposure);
+		}
+		if let Some(physical) = &self.physical {
+			camera.set_physical(physical);
+		}
+		Ok(camera)
+	}
 }
 
-impl From<SerializedCamera> for Camera {
-	fn from(value: SerializedCamera) -> Self {
-		Camera::from_look_at(
-			value.pos,
-			value.look_at,
-			value.aspect_ratio,
-			value.fov,
-			value.aperture,
-		)
+impl TryFrom<SerializedCamera> for Camera {
+	type Error = String;
+
+	fn try_from(value: SerializedCamera) -> Result<Self, Self::Error> {
+		value.into_camera(Vector3D::new(0.0, 0.0, 1.0))
+	}
+}
+
+impl From<Camera> for SerializedCamera {
+	fn from(value: Camera) -> Self {
+		// Physical cameras are saved as such, so that they keep their
+		// exposure when loaded.
+		let (fov, aperture, exposure) = match value.physical {
+			Some(_) => (None, 0.0, value.explicit_exposure),
+			None => (
+				Some(value.fov),
+				value.aperture,
+				(value.exposure != 1.0 || value.explicit_exposure.is_some())
+					.then_some(value.exposure),
+			),
+		};
+		Self {
+			pos: value.pos,
+			look_at: value.look_at(),
+			up: Some(value.up),
+			roll: value.roll,
+			aspect_ratio: value.fixed_aspect_ratio,
+			fov,
+			aperture,
+			aperture_shape: value.aperture_shape,
+			physical: value.physical,
+			exposure,
+			projection: value.projection,
+			shutter_open: value.shutter_open,
+			shutter_close: value.shutter_close,
+			up_transform: Transform::default(),
+		}
 	}
 }
@@ -0,0 +1,195 @@
+use std::{
+	fs::{self, File},
+	io::{self, BufReader, BufWriter, Read, Write},
+	path::Path,
+};
+
+use crate::{
+	scene::Scene,
+	util::{
+		read_array, read_region, read_scene_hash, read_u32, read_u64, write_region,
+		write_scene_hash,
+	},
+	Region,
+};
+
+const MAGIC: &[u8; 4] = b"PTCK";
+const VERSION: u32 = 2;
+
+/// The state of an unfinished render, from which rendering can be resumed.
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+	pub width: u32,
+	pub height: u32,
+
+	/// The part of the image that is rendered, or `None` for all of it.
+	pub region: Option<Region>,
+
+	/// The maximum number of bounces of each ray.
+	pub max_bounces: u32,
+
+	/// The [`scene_hash`] of the rendered scene.
+	pub scene_hash: Option<u64>,
+
+	/// The seed from which the random numbers of every pass are derived.
+	pub seed: u64,
+
+	/// The number of samples per pixel accumulated in `pixels`.
+	pub samples: u32,
+
+	/// The index of the first pass that has not been started yet. Resuming
+	/// continues from this pass, so that no pass is rendered twice with the
+	/// same random numbers.
+	pub next_pass: u32,
+
+	/// The accumulated pixel values, as in [`Pathtracer::pixels`](crate::Pathtracer::pixels).
+	pub pixels: Box<[u32]>,
+}
+
+impl Checkpoint {
+	/// Creates an empty checkpoint with a random seed.
+	pub fn new(
+		width: u32,
+		height: u32,
+		region: Option<Region>,
+		max_bounces: u32,
+		scene_hash: Option<u64>,
+	) -> Self {
+		Self {
+			width,
+			height,
+			region,
+			max_bounces,
+			scene_hash,
+			seed: rand::random(),
+			samples: 0,
+			next_pass: 0,
+			pixels: vec![0; (width * height * 4) as usize].into_boxed_slice(),
+		}
+	}
+
+	/// Checks that rendering can be resumed from this checkpoint with the
+	/// settings of `other`, describing the first difference if it can't.
+	pub fn check_resumable(&self, other: &Checkpoint) -> Result<(), String> {
+		if (self.width, self.height) != (other.width, other.height) {
+			return Err(format!(
+				"checkpoint was rendered at {}x{}, not {}x{}",
+				self.width, self.height, other.width, other.height
+			));
+		}
+		if self.region != other.region {
+			let describe = |region: Option<Region>| {
+				region.map_or("the whole image".to_string(), |r| format!("region {r}"))
+			};
+			return Err(format!(
+				"checkpoint was rendered for {}, not {}",
+				describe(self.region),
+				describe(other.region)
+			));
+		}
+		if self.max_bounces != other.max_bounces {
+			return Err(format!(
+				"checkpoint was rendered with {} bounces, not {}",
+				self.max_bounces, other.max_bounces
+			));
+		}
+		if self.scene_hash != other.scene_hash {
+			return Err("checkpoint was rendered from a different scene".to_string());
+		}
+		Ok(())
+	}
+
+	/// Adds the samples of a single rendered pass.
+	pub fn add(&mut self, pixels: &[u32]) {
+		for (sum, subpixel) in self.pixels.iter_mut().zip(pixels) {
+			*sum += subpixel;
+		}
+		self.samples += 1;
+	}
+
+	/// Writes the checkpoint to `path`. The file is replaced atomically, so an
+	/// interrupted write leaves the previous checkpoint intact.
+	pub fn save(&self, path: &Path) -> io::Result<()> {
+		let tmp_path = path.with_extension("tmp");
+		let mut writer = BufWriter::new(File::create(&tmp_path)?);
+		writer.write_all(MAGIC)?;
+		for value in [VERSION, self.width, self.height] {
+			writer.write_all(&value.to_le_bytes())?;
+		}
+		write_region(&mut writer, self.region)?;
+		writer.write_all(&self.max_bounces.to_le_bytes())?;
+		write_scene_hash(&mut writer, self.scene_hash)?;
+		writer.write_all(&self.seed.to_le_bytes())?;
+		for value in [self.samples, self.next_pass] {
+			writer.write_all(&value.to_le_bytes())?;
+		}
+		for value in self.pixels.iter() {
+			writer.write_all(&value.to_le_bytes())?;
+		}
+		writer.into_inner()?.sync_all()?;
+		fs::rename(tmp_path, path)
+	}
+
+	pub fn load(path: &Path) -> io::Result<Self> {
+		let mut reader = BufReader::new(File::open(path)?);
+
+		let mut magic = [0; 4];
+		reader.read_exact(&mut magic)?;
+		if &magic != MAGIC || read_u32(&mut reader)? != VERSION {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidData,
+				"not a checkpoint file, or from an incompatible version",
+			));
+		}
+
+		let width = read_u32(&mut reader)?;
+		let height = read_u32(&mut reader)?;
+		let region = read_region(&mut reader)?;
+		let max_bounces = read_u32(&mut reader)?;
+		let scene_hash = read_scene_hash(&mut reader)?;
+		let seed = read_u64(&mut reader)?;
+		let samples = read_u32(&mut reader)?;
+		let next_pass = read_u32(&mut reader)?;
+		let pixels = read_array(&mut reader, &[width, height, 4], read_u32)?;
+
+		Ok(Self {
+			width,
+			height,
+			region,
+			max_bounces,
+			scene_hash,
+			seed,
+			samples,
+			next_pass,
+			pixels,
+		})
+	}
+}
+
+/// Returns a hash of `scene` as it would be saved to a scene file, so that
+/// checkpoints can be matched to the scene they were rendered from. Images and
+/// meshes loaded from files are only identified by their path. Returns
+/// [`None`] for scenes that can't be saved, like glTF scenes with embedded
+/// textures.
+pub fn scene_hash(scene: &Scene) -> Option<u64> {
+	let mut hasher = Fnv1a(0xcbf2_9ce4_8422_2325);
+	serde_json::to_writer(&mut hasher, scene).ok()?;
+	Some(hasher.0)
+}
+
+/// The 64-bit FNV-1a hash, which unlike the hasher of the standard library is
+/// guaranteed not to change between Rust versions.
+struct Fnv1a(u64);
+
+impl Write for Fnv1a {
+	fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+		for &byte in bytes {
+			self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+		}
+		Ok(bytes.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
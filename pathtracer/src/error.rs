@@ -0,0 +1,86 @@
+use std::{fmt, io, path::PathBuf};
+
+#[derive(Debug)]
+pub enum Error {
+	/// A file could not be read.
+	Io { path: PathBuf, source: io::Error },
+
+	/// A file could not be written.
+	Write { path: PathBuf, source: io::Error },
+
+	/// An image file could not be decoded.
+	Image {
+		path: PathBuf,
+		source: png::DecodingError,
+	},
+
+	/// A glTF file could not be imported.
+	Gltf { path: PathBuf, source: gltf::Error },
+
+	/// A mesh file is invalid.
+	Mesh { path: PathBuf, message: String },
+
+	/// A scene file is invalid.
+	Scene {
+		path: Option<PathBuf>,
+		line: Option<usize>,
+		message: String,
+	},
+
+	/// A scene could not be serialized.
+	Serialize { message: String },
+
+	/// A scene built in code is incomplete or invalid.
+	InvalidScene { message: String },
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Error::Io { path, source } => write!(f, "could not read {}: {source}", path.display()),
+			Error::Write { path, source } => {
+				write!(f, "could not write {}: {source}", path.display())
+			}
+			Error::Image { path, source } => {
+				write!(f, "could not decode image {}: {source}", path.display())
+			}
+			Error::Gltf { path, source } => {
+				write!(f, "could not import glTF file {}: {source}", path.display())
+			}
+			Error::Mesh { path, message } => {
+				write!(f, "invalid mesh file {}: {message}", path.display())
+			}
+			Error::Scene {
+				path,
+				line,
+				message,
+			} => {
+				write!(f, "error in scene file")?;
+				if let Some(path) = path {
+					write!(f, " {}", path.display())?;
+				}
+				if let Some(line) = line {
+					write!(f, " at line {line}")?;
+				}
+				write!(f, ": {message}")
+			}
+			Error::Serialize { message } => write!(f, "could not serialize scene: {message}"),
+			Error::InvalidScene { message } => write!(f, "invalid scene: {message}"),
+		}
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Error::Io { source, .. } => Some(source),
+			Error::Write { source, .. } => Some(source),
+			Error::Image { source, .. } => Some(source),
+			Error::Gltf { source, .. } => Some(source),
+			Error::Mesh { .. }
+			| Error::Scene { .. }
+			| Error::Serialize { .. }
+			| Error::InvalidScene { .. } => None,
+		}
+	}
+}
@@ -0,0 +1,342 @@
+use core::f32::consts::PI;
+use std::{path::Path, sync::Arc};
+
+use ::gltf::{
+	camera::Projection as GltfProjection, image::Format, khr_lights_punctual::Kind, mesh::Mode,
+	Node,
+};
+use euclid::default::{Point3D, Transform3D, Vector2D, Vector3D};
+
+use crate::{
+	camera::{ApertureShape, Projection, SerializedCamera},
+	hittable::{HittableObject, Mesh, Sphere, Triangle},
+	material::Material,
+	scene::UpAxis,
+	texture::{AnyTexture, ImageTexture, SolidColour},
+	transform::Transform,
+	Error,
+};
+
+/// The radius of the spheres that stand in for point and spot lights. Smaller
+/// spheres are closer to a point, but make renders noisier.
+const LIGHT_RADIUS: f32 = 0.25;
+
+/// The distance and radius of the spheres that stand in for directional
+/// lights.
+const SUN_DISTANCE: f32 = 1000.0;
+const SUN_RADIUS: f32 = 10.0;
+
+/// The objects and camera imported from a glTF file.
+pub(crate) struct GltfScene {
+	pub objects: Vec<HittableObject>,
+
+	/// The first camera in the scene, if there is one.
+	pub camera: Option<SerializedCamera>,
+}
+
+/// Imports the default scene of a `.gltf` or `.glb` file.
+///
+/// Each primitive of a mesh becomes a [`Mesh`], and materials are mapped onto [`Material`] using
+/// their base colour, metallic, roughness, transmission, IOR and emission.
+/// As materials either reflect or emit light, emission is only used for
+/// materials with a black base colour, which become lights. Punctual lights
+/// become small emissive spheres, where spot lights shine in all directions.
+pub(crate) fn load(path: &Path) -> Result<GltfScene, Error> {
+	let (document, buffers, images) = ::gltf::import(path).map_err(|source| Error::Gltf {
+		path: path.to_path_buf(),
+		source,
+	})?;
+
+	let mut importer = Importer {
+		buffers,
+		images,
+		textures: vec![None; document.images().len()],
+		materials: vec![None; document.materials().len()],
+		default_material: None,
+		scene: GltfScene {
+			objects: Vec::new(),
+			camera: None,
+		},
+	};
+	if let Some(scene) = document
+		.default_scene()
+		.or_else(|| document.scenes().next())
+	{
+		for node in scene.nodes() {
+			importer.add_node(&node, &Transform3D::identity());
+		}
+	}
+	Ok(importer.scene)
+}
+
+struct Importer {
+	buffers: Vec<::gltf::buffer::Data>,
+	images: Vec<::gltf::image::Data>,
+
+	/// Textures and materials that have been converted so far, by index.
+	textures: Vec<Option<Arc<AnyTexture>>>,
+	materials: Vec<Option<Arc<Material>>>,
+	default_material: Option<Arc<Material>>,
+
+	scene: GltfScene,
+}
+
+impl Importer {
+	fn add_node(&mut self, node: &Node, parent: &Transform3D<f32>) {
+		let m = node.transform().matrix();
+		let transform = Transform3D::new(
+			m[0][0], m[0][1], m[0][2], m[0][3], m[1][0], m[1][1], m[1][2], m[1][3], m[2][0],
+			m[2][1], m[2][2], m[2][3], m[3][0], m[3][1], m[3][2], m[3][3],
+		)
+		.then(parent);
+
+		if let Some(mesh) = node.mesh() {
+			for primitive in mesh.primitives() {
+				let material = self.material(primitive.material());
+				let reader = primitive.reader(|buffer| Some(&self.buffers[buffer.index()]));
+				let Some(positions) = reader.read_positions() else {
+					continue;
+				};
+				let positions: Vec<_> = positions
+					.map(|p| transform_point(&transform, Point3D::from(p)))
+					.collect();
+				let uvs: Option<Vec<_>> = reader.read_tex_coords(0).map(|uvs| {
+					// glTF has the origin of textures at the top left
+					uvs.into_f32()
+						.map(|[u, v]| Vector2D::new(u, 1.0 - v))
+						.collect()
+				});
+				let indices: Vec<_> = match reader.read_indices() {
+					Some(indices) => indices.into_u32().map(|i| i as usize).collect(),
+					None => (0..positions.len()).collect(),
+				};
+
+				let triangles = triangles(primitive.mode(), &indices)
+					.into_iter()
+					.filter_map(|[a, b, c]| {
+						Some(Triangle {
+							a: *positions.get(a)?,
+							b: *positions.get(b)?,
+							c: *positions.get(c)?,
+							material: Arc::clone(&material),
+							velocity: Vector3D::zero(),
+							uv: uvs
+								.as_ref()
+								.and_then(|uvs| Some([*uvs.get(a)?, *uvs.get(b)?, *uvs.get(c)?])),
+							colours: None,
+						})
+					})
+					.collect();
+				self.scene.objects.push(Mesh::new(triangles).into());
+			}
+		}
+
+		let pos = transform_point(&transform, Point3D::origin());
+		let dir = transform.transform_vector3d(Vector3D::new(0.0, 0.0, -1.0));
+
+		if let (Some(camera), None) = (node.camera(), &self.scene.camera) {
+			let (fov, aspect_ratio, projection) = match camera.projection() {
+				GltfProjection::Perspective(p) => (
+					p.yfov().to_degrees(),
+					p.aspect_ratio(),
+					Projection::Perspective,
+				),
+				GltfProjection::Orthographic(o) => (
+					0.0,
+					Some(o.xmag() / o.ymag()),
+					Projection::Orthographic {
+						height: 2.0 * o.ymag(),
+					},
+				),
+			};
+			self.scene.camera = Some(SerializedCamera {
+				pos,
+				look_at: pos + dir.normalize(),
+				up: Some(transform.transform_vector3d(Vector3D::new(0.0, 1.0, 0.0))),
+				roll: 0.0,
+				aspect_ratio,
+				fov: Some(fov),
+				aperture: 0.0,
+				aperture_shape: ApertureShape::Circle,
+				physical: None,
+				exposure: None,
+				projection,
+				shutter_open: 0.0,
+				shutter_close: 0.0,
+				up_transform: Transform::default(),
+			});
+		}
+
+		if let Some(light) = node.light() {
+			let colour = Vector3D::from(light.color()) * light.intensity();
+			let (centre, radius, radiance) = match light.kind() {
+				// The intensity of a sphere is its radiance times its
+				// cross-section
+				Kind::Point | Kind::Spot { .. } => (
+					pos,
+					LIGHT_RADIUS,
+					colour / (PI * LIGHT_RADIUS * LIGHT_RADIUS),
+				),
+				// The illuminance from a distant sphere is its radiance times
+				// the solid angle it covers
+				Kind::Directional => (
+					Point3D::origin() - dir.normalize() * SUN_DISTANCE,
+					SUN_RADIUS,
+					colour / (PI * (SUN_RADIUS / SUN_DISTANCE).powi(2)),
+				),
+			};
+			let texture = SolidColour::new(radiance.x, radiance.y, radiance.z);
+			self.scene.objects.push(
+				Sphere {
+					centre,
+					radius,
+					material: Arc::new(Material::emissive(texture.into())),
+					velocity: Vector3D::zero(),
+					up_axis: UpAxis::default(),
+				}
+				.into(),
+			);
+		}
+
+		for child in node.children() {
+			self.add_node(&child, &transform);
+		}
+	}
+
+	fn material(&mut self, material: ::gltf::Material) -> Arc<Material> {
+		let cached = match material.index() {
+			Some(index) => &self.materials[index],
+			None => &self.default_material,
+		};
+		if let Some(cached) = cached {
+			return Arc::clone(cached);
+		}
+
+		let pbr = material.pbr_metallic_roughness();
+		let emission = Vector3D::from(material.emissive_factor())
+			* material.emissive_strength().unwrap_or(1.0);
+		let base_colour = pbr.base_color_factor();
+		let base_colour = Vector3D::new(base_colour[0], base_colour[1], base_colour[2]);
+		let emissive = emission != Vector3D::zero() && base_colour == Vector3D::zero();
+		let texture = if emissive {
+			Arc::new(SolidColour::new(emission.x, emission.y, emission.z).into())
+		} else {
+			pbr.base_color_texture()
+				.and_then(|info| self.texture(info.texture().source().index(), base_colour))
+				.unwrap_or_else(|| {
+					Arc::new(SolidColour::new(base_colour.x, base_colour.y, base_colour.z).into())
+				})
+		};
+
+		let converted = Arc::new(Material {
+			texture,
+			emission: if emissive { 1.0 } else { 0.0 },
+			metallic: pbr.metallic_factor(),
+			specular: 1.0,
+			transparency: material
+				.transmission()
+				.map_or(0.0, |transmission| transmission.transmission_factor()),
+			roughness: pbr.roughness_factor(),
+			ior: material.ior().unwrap_or(1.5),
+			..Material::default()
+		});
+		match material.index() {
+			Some(index) => self.materials[index] = Some(Arc::clone(&converted)),
+			None => self.default_material = Some(Arc::clone(&converted)),
+		}
+		converted
+	}
+
+	/// Converts the image with the given index into a texture with its colours
+	/// multiplied by `factor`, or returns [`None`] if its format is not
+	/// supported. Only unscaled textures are cached, as scaled ones are
+	/// usually used by a single material.
+	fn texture(&mut self, index: usize, factor: Vector3D<f32>) -> Option<Arc<AnyTexture>> {
+		let scaled = factor != Vector3D::one();
+		if let (false, Some(texture)) = (scaled, &self.textures[index]) {
+			return Some(Arc::clone(texture));
+		}
+
+		let image = &self.images[index];
+		let (channels, bytes_per_channel) = match image.format {
+			Format::R8 => (1, 1),
+			Format::R8G8 => (2, 1),
+			Format::R8G8B8 => (3, 1),
+			Format::R8G8B8A8 => (4, 1),
+			Format::R16 => (1, 2),
+			Format::R16G16 => (2, 2),
+			Format::R16G16B16 => (3, 2),
+			Format::R16G16B16A16 => (4, 2),
+			_ => return None,
+		};
+		let pixels = image
+			.pixels
+			.chunks_exact(channels * bytes_per_channel)
+			.flat_map(|pixel| {
+				// Only the most significant byte of 16-bit channels is kept
+				let channel = |i: usize| {
+					let i = i.min(channels.min(3) - 1);
+					if bytes_per_channel == 2 {
+						(u16::from_ne_bytes([pixel[2 * i], pixel[2 * i + 1]]) >> 8) as u8
+					} else {
+						pixel[i]
+					}
+				};
+				// Grayscale images have only one colour channel
+				let colour = if channels < 3 {
+					[channel(0); 3]
+				} else {
+					[channel(0), channel(1), channel(2)]
+				};
+				[0, 1, 2].map(|i| (colour[i] as f32 * factor.to_array()[i]).round() as u8)
+			})
+			.collect();
+
+		let texture = Arc::new(ImageTexture::new(pixels, image.width, image.height).into());
+		if !scaled {
+			self.textures[index] = Some(Arc::clone(&texture));
+		}
+		Some(texture)
+	}
+}
+
+/// Transforms a point by a node transform, which is affine.
+fn transform_point(transform: &Transform3D<f32>, point: Point3D<f32>) -> Point3D<f32> {
+	transform.transform_point3d(point).unwrap_or(point)
+}
+
+/// Returns the vertex indices of each triangle in a primitive. Points and
+/// lines have no surface and are skipped.
+fn triangles(mode: Mode, indices: &[usize]) -> Vec<[usize; 3]> {
+	match mode {
+		Mode::Triangles => indices
+			.chunks_exact(3)
+			.map(|t| [t[0], t[1], t[2]])
+			.collect(),
+		// Every other triangle in a strip is flipped to keep the winding order
+		Mode::TriangleStrip => indices
+			.windows(3)
+			.enumerate()
+			.map(|(i, t)| {
+				if i % 2 == 0 {
+					[t[0], t[1], t[2]]
+				} else {
+					[t[1], t[0], t[2]]
+				}
+			})
+			.collect(),
+		Mode::TriangleFan => indices
+			.windows(2)
+			.skip(1)
+			.map(|t| [indices[0], t[0], t[1]])
+			.collect(),
+		Mode::Points | Mode::Lines | Mode::LineLoop | Mode::LineStrip => Vec::new(),
+	}
+}
+
+/// Returns whether `path` has the extension of a glTF file.
+pub(crate) fn is_gltf(path: &Path) -> bool {
+	path.extension().is_some_and(|extension| {
+		extension.eq_ignore_ascii_case("gltf") || extension.eq_ignore_ascii_case("glb")
+	})
+}
@@ -1,9 +1,15 @@
 use core::{f32::consts::PI, ops::Range};
+use std::{
+	path::{Path, PathBuf},
+	sync::Arc,
+};
 
 use euclid::default::{Point3D, Vector2D, Vector3D};
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer, Serialize};
 
-use crate::{material::Material, ray::Ray};
+use crate::{
+	assets, library, material::Material, mesh, ray::Ray, scene::UpAxis, transform::Transform, Error,
+};
 
 #[derive(Debug)]
 pub struct Hit<'a> {
@@ -12,6 +18,9 @@ pub struct Hit<'a> {
 	pub distance: f32,
 	pub material: &'a Material,
 	pub uv: Vector2D<f32>,
+
+	/// The colour blended from the vertex colours of a mesh, if it has them.
+	pub vertex_colour: Option<Vector3D<f32>>,
 }
 
 impl<'a> Hit<'a> {
@@ -28,6 +37,7 @@ impl<'a> Hit<'a> {
 			distance,
 			material,
 			uv,
+			vertex_colour: None,
 		}
 	}
 }
@@ -35,30 +45,74 @@ impl<'a> Hit<'a> {
 pub trait Hittable {
 	/// Returns the distance at which the `ray` hits this [`Hittable`], or
 	/// [`None`] if the `ray` does not hit this object within the given `range`.
-	fn hit(&self, ray: &Ray, range: Range<f32>) -> Option<Hit>;
+	fn hit(&self, ray: &Ray, range: Range<f32>) -> Option<Hit<'_>>;
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(tag = "type")]
 pub enum HittableObject {
 	Sphere(Sphere),
 	Triangle(Triangle),
+	Mesh(Mesh),
+}
+
+impl HittableObject {
+	/// Moves, rotates and scales this object, including its velocity.
+	pub fn transform(&mut self, transform: &Transform) {
+		match self {
+			HittableObject::Sphere(s) => s.transform(transform),
+			HittableObject::Triangle(t) => t.transform(transform),
+			HittableObject::Mesh(m) => m.transform(transform),
+		}
+	}
+
+	/// Gives the whole object the same material.
+	pub fn set_material(&mut self, material: Arc<Material>) {
+		match self {
+			HittableObject::Sphere(s) => s.material = material,
+			HittableObject::Triangle(t) => t.material = material,
+			HittableObject::Mesh(m) => m.set_material(material),
+		}
+	}
 }
 
 impl Hittable for HittableObject {
-	fn hit(&self, ray: &Ray, range: Range<f32>) -> Option<Hit> {
+	fn hit(&self, ray: &Ray, range: Range<f32>) -> Option<Hit<'_>> {
 		match self {
 			HittableObject::Sphere(s) => s.hit(ray, range),
 			HittableObject::Triangle(t) => t.hit(ray, range),
+			HittableObject::Mesh(m) => m.hit(ray, range),
 		}
 	}
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Sphere {
 	pub centre: Point3D<f32>,
 	pub radius: f32,
-	pub material: Material,
+	#[serde(deserialize_with = "library::deserialize")]
+	pub material: Arc<Material>,
+
+	/// The distance the centre moves per unit of time.
+	#[serde(default)]
+	pub velocity: Vector3D<f32>,
+
+	/// The axis that textures are wrapped around. This is set from the scene.
+	#[serde(skip)]
+	pub up_axis: UpAxis,
+}
+
+impl Sphere {
+	pub fn transform(&mut self, transform: &Transform) {
+		self.centre = transform.point(self.centre);
+		self.radius *= transform.scale;
+		self.velocity = transform.vector(self.velocity);
+	}
+
+	/// Returns the position of the centre at the given `time`.
+	pub fn centre(&self, time: f32) -> Point3D<f32> {
+		self.centre + self.velocity * time
+	}
 }
 
 impl From<Sphere> for HittableObject {
@@ -68,8 +122,9 @@ impl From<Sphere> for HittableObject {
 }
 
 impl Hittable for Sphere {
-	fn hit(&self, ray: &Ray, range: Range<f32>) -> Option<Hit> {
-		let oc = ray.origin - self.centre;
+	fn hit(&self, ray: &Ray, range: Range<f32>) -> Option<Hit<'_>> {
+		let centre = self.centre(ray.time);
+		let oc = ray.origin - centre;
 		let a = ray.dir.square_length();
 		let half_b = oc.dot(ray.dir);
 		let c = oc.square_length() - self.radius * self.radius;
@@ -89,9 +144,13 @@ impl Hittable for Sphere {
 		}
 
 		let point = ray.at(distance);
-		let outward_normal = ((point - self.centre) / self.radius).normalize();
-		let theta = (-outward_normal.z).acos();
-		let phi = outward_normal.y.atan2(outward_normal.x) + PI;
+		let outward_normal = ((point - centre) / self.radius).normalize();
+		let (up, forward) = match self.up_axis {
+			UpAxis::Y => (outward_normal.y, -outward_normal.z),
+			UpAxis::Z => (outward_normal.z, outward_normal.y),
+		};
+		let theta = (-up).acos();
+		let phi = forward.atan2(outward_normal.x) + PI;
 
 		Some(Hit::new(
 			point,
@@ -103,12 +162,105 @@ impl Hittable for Sphere {
 	}
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(try_from = "SerializedTriangle", into = "SerializedTriangle")]
 pub struct Triangle {
 	pub a: Point3D<f32>,
 	pub b: Point3D<f32>,
 	pub c: Point3D<f32>,
-	pub material: Material,
+	pub material: Arc<Material>,
+
+	/// The distance the vertices move per unit of time.
+	pub velocity: Vector3D<f32>,
+
+	/// The texture coordinates of `a`, `b` and `c`. Without them, textures
+	/// are mapped using the position within the triangle.
+	pub uv: Option<[Vector2D<f32>; 3]>,
+
+	/// The colours of `a`, `b` and `c`, for use with
+	/// [`VertexColour`](crate::texture::VertexColour) textures.
+	pub colours: Option<[Vector3D<f32>; 3]>,
+}
+
+impl Triangle {
+	pub fn transform(&mut self, transform: &Transform) {
+		self.a = transform.point(self.a);
+		self.b = transform.point(self.b);
+		self.c = transform.point(self.c);
+		self.velocity = transform.vector(self.velocity);
+	}
+
+	/// Returns the texture coordinates at the point `u` along the edge from
+	/// `a` to `b` and `v` along the edge from `a` to `c`.
+	fn uv(&self, u: f32, v: f32) -> Vector2D<f32> {
+		match self.uv {
+			Some([uv_a, uv_b, uv_c]) => uv_a * (1.0 - u - v) + uv_b * u + uv_c * v,
+			None => Vector2D::new(u, v),
+		}
+	}
+}
+
+/// A triangle in a scene file, where the material may be left out for
+/// triangles of a mesh that has one.
+#[derive(Debug, Deserialize, Serialize)]
+struct SerializedTriangle {
+	a: Point3D<f32>,
+	b: Point3D<f32>,
+	c: Point3D<f32>,
+	#[serde(
+		default,
+		deserialize_with = "deserialize_material",
+		skip_serializing_if = "Option::is_none"
+	)]
+	material: Option<Arc<Material>>,
+	#[serde(default)]
+	velocity: Vector3D<f32>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	uv: Option<[Vector2D<f32>; 3]>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	colours: Option<[Vector3D<f32>; 3]>,
+}
+
+impl SerializedTriangle {
+	/// Builds the triangle, using `default_material` if the triangle does not
+	/// specify one.
+	fn into_triangle(self, default_material: Option<&Arc<Material>>) -> Result<Triangle, String> {
+		let material = self
+			.material
+			.or_else(|| default_material.cloned())
+			.ok_or("missing field `material`")?;
+		Ok(Triangle {
+			a: self.a,
+			b: self.b,
+			c: self.c,
+			material,
+			velocity: self.velocity,
+			uv: self.uv,
+			colours: self.colours,
+		})
+	}
+}
+
+impl TryFrom<SerializedTriangle> for Triangle {
+	type Error = String;
+
+	fn try_from(value: SerializedTriangle) -> Result<Self, Self::Error> {
+		value.into_triangle(None)
+	}
+}
+
+impl From<Triangle> for SerializedTriangle {
+	fn from(value: Triangle) -> Self {
+		Self {
+			a: value.a,
+			b: value.b,
+			c: value.c,
+			material: Some(value.material),
+			velocity: value.velocity,
+			uv: value.uv,
+			colours: value.colours,
+		}
+	}
 }
 
 impl From<Triangle> for HittableObject {
@@ -120,7 +272,9 @@ impl From<Triangle> for HittableObject {
 impl Hittable for Triangle {
 	// https://en.wikipedia.org/wiki/M%C3%B6ller%E2%80%93Trumbore_intersection_algorithm
 	// https://stackoverflow.com/a/42752998
-	fn hit(&self, ray: &Ray, range: Range<f32>) -> Option<Hit> {
+	fn hit(&self, ray: &Ray, range: Range<f32>) -> Option<Hit<'_>> {
+		let offset = self.velocity * ray.time;
+		let a = self.a + offset;
 		let edge1 = self.b - self.a;
 		let edge2 = self.c - self.a;
 		let normal = edge1.cross(edge2);
@@ -129,21 +283,243 @@ impl Hittable for Triangle {
 			return None; // ray is parallel
 		}
 
-		let ao = ray.origin - self.a;
+		let ao = ray.origin - a;
 		let dao = ao.cross(ray.dir);
 		let u = edge2.dot(dao) / det;
 		let v = -edge1.dot(dao) / det;
 		let distance = ao.dot(normal) / det;
 		if range.contains(&distance) && u >= 0.0 && v >= 0.0 && u + v <= 1.0 {
-			Some(Hit::new(
+			let mut hit = Hit::new(
 				ray.at(distance),
 				normal.normalize(),
 				distance,
 				&self.material,
-				Vector2D::new(u, v),
-			))
+				self.uv(u, v),
+			);
+			hit.vertex_colour = self.colours.map(|[colour_a, colour_b, colour_c]| {
+				colour_a * (1.0 - u - v) + colour_b * u + colour_c * v
+			});
+			Some(hit)
 		} else {
 			None
 		}
 	}
 }
+
+/// A group of triangles, like a mesh loaded from a PLY or STL file.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(try_from = "SerializedMesh", into = "SerializedMesh")]
+pub struct Mesh {
+	triangles: Vec<Triangle>,
+
+	/// The opposite corners of the box around all triangles, so that rays
+	/// that miss the box can skip the triangles. [`None`] if the triangles
+	/// are moving.
+	bounds: Option<[Point3D<f32>; 2]>,
+
+	/// The file the mesh was loaded from, so that it is saved as a reference
+	/// to that file. [`None`] for meshes built from triangles.
+	source: Option<MeshSource>,
+}
+
+#[derive(Debug, Clone)]
+struct MeshSource {
+	path: PathBuf,
+	material: Arc<Material>,
+	transform: Transform,
+}
+
+impl Mesh {
+	pub fn new(triangles: Vec<Triangle>) -> Self {
+		let moving = triangles.iter().any(|t| t.velocity != Vector3D::zero());
+		let bounds = (!moving && !triangles.is_empty()).then(|| {
+			let points = triangles.iter().flat_map(|t| [t.a, t.b, t.c]);
+			let min = points
+				.clone()
+				.fold(Point3D::splat(f32::INFINITY), Point3D::min);
+			let max = points.fold(Point3D::splat(f32::NEG_INFINITY), Point3D::max);
+			[min, max]
+		});
+		Self {
+			triangles,
+			bounds,
+			source: None,
+		}
+	}
+
+	/// Loads a mesh from a PLY or STL file, giving every triangle the same
+	/// material. Vertex colours in PLY files are kept.
+	pub fn load(path: &Path, material: Arc<Material>) -> Result<Self, Error> {
+		let data = mesh::load(path)?;
+		let source = MeshSource {
+			path: path.to_path_buf(),
+			material: Arc::clone(&material),
+			transform: Transform::default(),
+		};
+		let triangles = data
+			.faces
+			.iter()
+			.map(|&[a, b, c]| Triangle {
+				a: data.positions[a],
+				b: data.positions[b],
+				c: data.positions[c],
+				material: Arc::clone(&material),
+				velocity: Vector3D::zero(),
+				uv: None,
+				colours: data
+					.colours
+					.as_ref()
+					.map(|colours| [colours[a], colours[b], colours[c]]),
+			})
+			.collect();
+		Ok(Self {
+			source: Some(source),
+			..Self::new(triangles)
+		})
+	}
+
+	pub fn triangles(&self) -> &[Triangle] {
+		&self.triangles
+	}
+
+	/// Gives every triangle the same material.
+	pub fn set_material(&mut self, material: Arc<Material>) {
+		for triangle in &mut self.triangles {
+			triangle.material = Arc::clone(&material);
+		}
+		if let Some(source) = &mut self.source {
+			source.material = material;
+		}
+	}
+
+	pub fn transform(&mut self, transform: &Transform) {
+		let mut triangles = core::mem::take(&mut self.triangles);
+		for triangle in &mut triangles {
+			triangle.transform(transform);
+		}
+		let source = self.source.take().map(|source| MeshSource {
+			transform: source.transform.then(transform),
+			..source
+		});
+		*self = Mesh {
+			source,
+			..Mesh::new(triangles)
+		};
+	}
+
+	/// Returns whether the ray passes through the bounding box within `range`.
+	fn hits_bounds(&self, ray: &Ray, range: &Range<f32>) -> bool {
+		let Some([min, max]) = self.bounds else {
+			return true;
+		};
+		let (origin, dir) = (ray.origin.to_array(), ray.dir.to_array());
+		let (min, max) = (min.to_array(), max.to_array());
+		let (mut start, mut end) = (range.start, range.end);
+		for axis in 0..3 {
+			let inverse = 1.0 / dir[axis];
+			let near = (min[axis] - origin[axis]) * inverse;
+			let far = (max[axis] - origin[axis]) * inverse;
+			start = start.max(near.min(far));
+			end = end.min(near.max(far));
+			if end < start {
+				return false;
+			}
+		}
+		true
+	}
+}
+
+impl From<Mesh> for HittableObject {
+	fn from(value: Mesh) -> Self {
+		HittableObject::Mesh(value)
+	}
+}
+
+impl Hittable for Mesh {
+	fn hit(&self, ray: &Ray, range: Range<f32>) -> Option<Hit<'_>> {
+		if !self.hits_bounds(ray, &range) {
+			return None;
+		}
+
+		let mut closest_hit = None;
+		for triangle in &self.triangles {
+			let max_distance = closest_hit.as_ref().map_or(range.end, |h: &Hit| h.distance);
+			if let Some(hit) = triangle.hit(ray, range.start..max_distance) {
+				closest_hit = Some(hit);
+			}
+		}
+		closest_hit
+	}
+}
+
+/// A mesh in a scene file, which is either loaded from a file or given as a
+/// list of triangles.
+#[derive(Debug, Deserialize, Serialize)]
+struct SerializedMesh {
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	path: Option<PathBuf>,
+	#[serde(
+		default,
+		deserialize_with = "deserialize_material",
+		skip_serializing_if = "Option::is_none"
+	)]
+	material: Option<Arc<Material>>,
+	/// The triangles, which use `material` unless they have their own.
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	triangles: Vec<SerializedTriangle>,
+	#[serde(flatten, skip_serializing_if = "Transform::is_identity")]
+	transform: Transform,
+}
+
+fn deserialize_material<'de, D: Deserializer<'de>>(
+	deserializer: D,
+) -> Result<Option<Arc<Material>>, D::Error> {
+	library::deserialize(deserializer).map(Some)
+}
+
+impl From<Mesh> for SerializedMesh {
+	fn from(value: Mesh) -> Self {
+		match value.source {
+			Some(source) => Self {
+				path: Some(assets::saved_path(&source.path)),
+				material: Some(source.material),
+				triangles: Vec::new(),
+				transform: source.transform,
+			},
+			None => Self {
+				path: None,
+				material: None,
+				triangles: value.triangles.into_iter().map(Into::into).collect(),
+				transform: Transform::default(),
+			},
+		}
+	}
+}
+
+impl TryFrom<SerializedMesh> for Mesh {
+	type Error = String;
+
+	fn try_from(value: SerializedMesh) -> Result<Self, Self::Error> {
+		let mut mesh = match value.path {
+			Some(_) if !value.triangles.is_empty() => {
+				return Err("a mesh cannot have both a path and triangles".to_string())
+			}
+			Some(path) => {
+				let material = value.material.ok_or("missing field `material`")?;
+				Mesh::load(&assets::resolve(&path), material).map_err(|err| err.to_string())?
+			}
+			None if value.triangles.is_empty() => {
+				return Err("a mesh needs either `path` or `triangles`".to_string())
+			}
+			None => Mesh::new(
+				value
+					.triangles
+					.into_iter()
+					.map(|triangle| triangle.into_triangle(value.material.as_ref()))
+					.collect::<Result<_, _>>()?,
+			),
+		};
+		mesh.transform(&value.transform);
+		Ok(mesh)
+	}
+}
@@ -1,11 +1,25 @@
+pub mod assets;
 pub mod camera;
+pub mod checkpoint;
+mod error;
+mod gltf;
 pub mod hittable;
+mod library;
 pub mod material;
+pub mod medium;
+mod mesh;
+pub mod raw;
 mod ray;
 pub mod scene;
 pub mod texture;
+pub mod transform;
 mod util;
 
+use core::{fmt, str::FromStr, time::Duration};
+use std::time::Instant;
+
+pub use error::Error;
+use euclid::default::Vector3D;
 use scene::Scene;
 
 #[derive(Debug)]
@@ -16,10 +30,19 @@ pub struct Pathtracer {
 	pub scene: Scene,
 	pub pixels: Box<[u32]>,
 	pub n_iterations: u32,
+
+	/// The part of the canvas that gets rendered. Pixels outside of this
+	/// region are left empty.
+	pub region: Region,
+
+	/// The seed from which the random numbers of [`Pathtracer::render_pass`]
+	/// are derived.
+	pub seed: u64,
 }
 
 impl Pathtracer {
-	pub fn new(width: u32, height: u32, max_bounces: u32, scene: Scene) -> Self {
+	pub fn new(width: u32, height: u32, max_bounces: u32, mut scene: Scene) -> Self {
+		scene.camera.set_resolution(width, height);
 		Self {
 			canvas_width: width,
 			canvas_height: height,
@@ -27,24 +50,36 @@ impl Pathtracer {
 			scene,
 			pixels: vec![0; (width * height * 4) as usize].into_boxed_slice(),
 			n_iterations: 0,
+			region: Region::new(0, 0, width, height),
+			seed: rand::random(),
 		}
 	}
 
 	pub fn render_single(&mut self) {
-		for y in 0..self.canvas_height {
-			for x in 0..self.canvas_width {
+		let region = self.region.clamp(self.canvas_width, self.canvas_height);
+		for y in region.y..region.y + region.height {
+			for x in region.x..region.x + region.width {
 				let u =
 					(x as f32 + util::random_in_range(-0.5, 0.5)) / (self.canvas_width - 1) as f32;
 				let v = 1.0
 					- (y as f32 + util::random_in_range(-0.5, 0.5))
 						/ (self.canvas_height - 1) as f32;
 
-				let ray = self.scene.camera.get_ray(u, v);
-				let colour = ray.cast(&self.scene, self.scene.background_colour, self.max_bounces);
+				let colour = match self.scene.camera.get_ray(u, v) {
+					Some(ray) => ray.cast(
+						&self.scene,
+						self.scene.background_colour,
+						self.scene.medium.as_ref(),
+						None,
+						self.max_bounces,
+					),
+					None => Vector3D::zero(),
+				};
+				let colour = colour * self.scene.camera.exposure();
 				let pixel_idx = util::coords_to_idx(x, y, self.canvas_width);
 
 				let colour_arr = util::colour_f32_to_u32(colour);
-				self.pixels[pixel_idx + 0] += colour_arr[0];
+				self.pixels[pixel_idx] += colour_arr[0];
 				self.pixels[pixel_idx + 1] += colour_arr[1];
 				self.pixels[pixel_idx + 2] += colour_arr[2];
 			}
@@ -52,29 +87,62 @@ impl Pathtracer {
 		self.n_iterations += 1;
 	}
 
+	/// Renders a single sample per pixel like [`Pathtracer::render_single`],
+	/// with random numbers that only depend on the seed and the index of the
+	/// `pass`. Rendering the same pass twice gives the same result.
+	pub fn render_pass(&mut self, pass: u32) {
+		util::seed_rng(self.seed, pass as u64);
+		self.render_single();
+	}
+
 	pub fn draw(&self, canvas: &mut [u8]) {
 		draw_pixels_to_canvas(&self.pixels, canvas, self.n_iterations);
+		self.region.clear_outside(canvas, self.canvas_width);
+	}
+
+	pub fn render(&mut self, canvas: &mut [u8], samples_per_pixel: u32, progress_cb: impl Fn()) {
+		debug_assert_eq!(
+			canvas.len(),
+			(self.canvas_width * self.canvas_height * 4) as usize
+		);
+
+		self.n_iterations = 0;
+
+		for _i in 0..samples_per_pixel {
+			self.render_single();
+			progress_cb();
+		}
+
+		self.draw(canvas);
 	}
 
-	pub fn render(
+	/// Keeps rendering passes until `time_limit` has passed, and returns the
+	/// number of samples per pixel that was reached. Always renders at least
+	/// one pass.
+	pub fn render_for(
 		&mut self,
 		canvas: &mut [u8],
-		samples_per_pixel: u32,
-		progress_cb: impl Fn() -> (),
-	) {
+		time_limit: Duration,
+		progress_cb: impl Fn(),
+	) -> u32 {
 		debug_assert_eq!(
 			canvas.len(),
 			(self.canvas_width * self.canvas_height * 4) as usize
 		);
 
 		self.n_iterations = 0;
+		let start_time = Instant::now();
 
-		for _i in 0..samples_per_pixel {
+		loop {
 			self.render_single();
 			progress_cb();
+			if start_time.elapsed() >= time_limit {
+				break;
+			}
 		}
 
 		self.draw(canvas);
+		self.n_iterations
 	}
 }
 
@@ -83,9 +151,80 @@ pub fn draw_pixels_to_canvas(pixels: &[u32], canvas: &mut [u8], scale: u32) {
 
 	for idx in (0..pixels.len()).step_by(4) {
 		let output_colour = util::colour_scale_sqrt(&pixels[idx..idx + 3], scale);
-		canvas[idx + 0] = output_colour[0];
+		canvas[idx] = output_colour[0];
 		canvas[idx + 1] = output_colour[1];
 		canvas[idx + 2] = output_colour[2];
 		canvas[idx + 3] = 255;
 	}
 }
+
+/// A rectangular part of the canvas, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+	pub x: u32,
+	pub y: u32,
+	pub width: u32,
+	pub height: u32,
+}
+
+impl Region {
+	pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+		Self {
+			x,
+			y,
+			width,
+			height,
+		}
+	}
+
+	pub fn contains(&self, x: u32, y: u32) -> bool {
+		(self.x..self.x.saturating_add(self.width)).contains(&x)
+			&& (self.y..self.y.saturating_add(self.height)).contains(&y)
+	}
+
+	/// Returns the part of this region that lies within a canvas of the given
+	/// size.
+	pub fn clamp(&self, width: u32, height: u32) -> Self {
+		let x = self.x.min(width);
+		let y = self.y.min(height);
+		Self {
+			x,
+			y,
+			width: self.width.min(width - x),
+			height: self.height.min(height - y),
+		}
+	}
+
+	/// Makes all pixels outside of this region transparent black.
+	pub fn clear_outside(&self, canvas: &mut [u8], width: u32) {
+		for (idx, pixel) in canvas.chunks_exact_mut(4).enumerate() {
+			let (x, y) = (idx as u32 % width, idx as u32 / width);
+			if !self.contains(x, y) {
+				pixel.fill(0);
+			}
+		}
+	}
+}
+
+impl fmt::Display for Region {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{},{},{},{}", self.x, self.y, self.width, self.height)
+	}
+}
+
+impl FromStr for Region {
+	type Err = String;
+
+	/// Parses a region in the format `x,y,width,height`.
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let values = s
+			.split(',')
+			.map(|v| v.trim().parse::<u32>().map_err(|err| err.to_string()))
+			.collect::<Result<Vec<_>, _>>()?;
+		match values[..] {
+			[_, _, 0, _] | [_, _, _, 0] => Err("the region is empty".to_string()),
+			[x, y, width, height] => Ok(Region::new(x, y, width, height)),
+			_ => Err("expected region in the format `x,y,width,height`".to_string()),
+		}
+	}
+}
@@ -0,0 +1,257 @@
+use std::{cell::RefCell, collections::HashMap, fmt, marker::PhantomData, sync::Arc};
+
+use serde::{
+	de::{self, value::MapAccessDeserializer, DeserializeOwned, MapAccess, Visitor},
+	Deserialize, Deserializer,
+};
+
+use crate::{
+	assets::{self, AssetPaths, Restore},
+	material::Material,
+	texture::AnyTexture,
+	Error,
+};
+
+thread_local! {
+	static CURRENT: RefCell<Library> = RefCell::new(Library::default());
+}
+
+/// The named materials and textures defined at the top level of a scene file,
+/// which objects can refer to by name.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct Library {
+	#[serde(default)]
+	textures: HashMap<String, Entry<AnyTexture>>,
+	#[serde(default)]
+	materials: HashMap<String, Entry<Material>>,
+}
+
+/// A named item, which is only deserialized once it is first used so that
+/// named items can refer to each other regardless of their order. Unloaded
+/// items keep the asset paths of the file they were defined in, and are stored
+/// as JSON values so that they can come from any scene format.
+#[derive(Debug)]
+pub(crate) enum Entry<T> {
+	Unloaded(serde_json::Value, AssetPaths),
+	Loading,
+	Loaded(Arc<T>),
+}
+
+impl<'de, T> Deserialize<'de> for Entry<T> {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		serde_json::Value::deserialize(deserializer)
+			.map(|value| Entry::Unloaded(value, assets::current()))
+	}
+}
+
+impl Library {
+	/// Adds the items of `other` that do not have the same name as an item in
+	/// this library.
+	pub(crate) fn extend(&mut self, other: Library) {
+		for (name, entry) in other.textures {
+			self.textures.entry(name).or_insert(entry);
+		}
+		for (name, entry) in other.materials {
+			self.materials.entry(name).or_insert(entry);
+		}
+	}
+
+	/// Runs `f` with this library in use by [`deserialize`], and checks that
+	/// all named items are valid, even if they are not used.
+	pub(crate) fn scope<T>(self, f: impl FnOnce() -> Result<T, Error>) -> Result<T, Error> {
+		let _restore = Restore::replace(&CURRENT, self);
+		f().and_then(|value| {
+			let (textures, materials) = CURRENT.with(|current| {
+				let library = current.borrow();
+				let textures: Vec<_> = library.textures.keys().cloned().collect();
+				let materials: Vec<_> = library.materials.keys().cloned().collect();
+				(textures, materials)
+			});
+			let scene_error = |message| Error::Scene {
+				path: None,
+				line: None,
+				message,
+			};
+			for name in textures {
+				AnyTexture::get(&name).map_err(scene_error)?;
+			}
+			for name in materials {
+				Material::get(&name).map_err(scene_error)?;
+			}
+			Ok(value)
+		})
+	}
+}
+
+/// An item that can be defined by name in the [`Library`].
+pub(crate) trait Named: DeserializeOwned {
+	/// What the item is called in error messages.
+	const KIND: &'static str;
+
+	fn entries(library: &mut Library) -> &mut HashMap<String, Entry<Self>>;
+
+	/// Returns the built-in item called `name`, which is used if the library
+	/// has no item with that name.
+	fn builtin(_name: &str) -> Option<Self> {
+		None
+	}
+
+	/// Returns the item called `name`, deserializing it if this is its first use.
+	fn get(name: &str) -> Result<Arc<Self>, String> {
+		let value = CURRENT.with(|current| {
+			let mut library = current.borrow_mut();
+			let entries = Self::entries(&mut library);
+			if !entries.contains_key(name) {
+				let item = Self::builtin(name)
+					.ok_or_else(|| format!("unknown {} `{name}`", Self::KIND))?;
+				entries.insert(name.to_string(), Entry::Loaded(Arc::new(item)));
+			}
+			let entry = entries.get_mut(name).unwrap();
+			match core::mem::replace(entry, Entry::Loading) {
+				Entry::Unloaded(value, asset_paths) => Ok(Some((value, asset_paths))),
+				Entry::Loading => Err(format!("{} `{name}` refers to itself", Self::KIND)),
+				Entry::Loaded(item) => {
+					*entry = Entry::Loaded(item);
+					Ok(None)
+				}
+			}
+		})?;
+
+		// The library must not be borrowed while deserializing, as the item may
+		// refer to other named items.
+		if let Some((value, asset_paths)) = value {
+			let item = asset_paths
+				.scope(|| Self::deserialize(value))
+				.map_err(|err| format!("in {} `{name}`: {err}", Self::KIND))?;
+			CURRENT.with(|current| {
+				Self::entries(&mut current.borrow_mut())
+					.insert(name.to_string(), Entry::Loaded(Arc::new(item)))
+			});
+		}
+
+		CURRENT.with(
+			|current| match Self::entries(&mut current.borrow_mut()).get(name) {
+				Some(Entry::Loaded(item)) => Ok(Arc::clone(item)),
+				_ => unreachable!(),
+			},
+		)
+	}
+}
+
+impl Named for AnyTexture {
+	const KIND: &'static str = "texture";
+
+	fn entries(library: &mut Library) -> &mut HashMap<String, Entry<Self>> {
+		&mut library.textures
+	}
+}
+
+impl Named for Material {
+	const KIND: &'static str = "material";
+
+	fn entries(library: &mut Library) -> &mut HashMap<String, Entry<Self>> {
+		&mut library.materials
+	}
+
+	fn builtin(name: &str) -> Option<Self> {
+		Material::preset(name)
+	}
+}
+
+/// Deserializes either the name of an item from the [`Library`], or an item
+/// defined in place. For use with `#[serde(deserialize_with)]`.
+pub(crate) fn deserialize<'de, D, T>(deserializer: D) -> Result<Arc<T>, D::Error>
+where
+	D: Deserializer<'de>,
+	T: Named,
+{
+	struct NamedVisitor<T>(PhantomData<T>);
+
+	impl<'de, T: Named> Visitor<'de> for NamedVisitor<T> {
+		type Value = Arc<T>;
+
+		fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+			write!(f, "the name of a {} or a table", T::KIND)
+		}
+
+		fn visit_str<E: de::Error>(self, name: &str) -> Result<Self::Value, E> {
+			T::get(name).map_err(E::custom)
+		}
+
+		fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+			T::deserialize(MapAccessDeserializer::new(map)).map(Arc::new)
+		}
+	}
+
+	deserializer.deserialize_any(NamedVisitor(PhantomData))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{hittable::HittableObject, scene::Scene};
+
+	const CAMERA: &str =
+		"[camera]\npos = [0.0, -2.0, 0.0]\nlook_at = [0.0, 0.0, 0.0]\nfov = 40.0\n";
+
+	/// Loads a scene with the given objects and named items.
+	fn load(objects: &str, items: &str) -> Result<Scene, Error> {
+		Scene::from_toml_str(&format!(
+			"background_colour = [0.0, 0.0, 0.0]\n{objects}\n{items}\n{CAMERA}"
+		))
+	}
+
+	fn load_error(objects: &str, items: &str) -> String {
+		match load(objects, items) {
+			Ok(_) => panic!("expected an error"),
+			Err(err) => err.to_string(),
+		}
+	}
+
+	const SPHERE: &str = "[[objects]]\ntype = \"Sphere\"\ncentre = [0.0, 0.0, 0.0]\nradius = 1.0\n";
+
+	#[test]
+	fn items_can_refer_to_later_items_and_are_shared() {
+		let scene = load(
+			&format!("{SPHERE}material = \"ground\"\n{SPHERE}material = \"ground\"\n"),
+			"[materials.ground]\ntexture = \"checker\"\n\
+			[textures.checker]\ntype = \"CheckerTexture\"\nscale = 1.0\neven = \"white\"\n\
+			odd = { type = \"SolidColour\", colour = [0.0, 0.0, 0.0] }\n\
+			[textures.white]\ntype = \"SolidColour\"\ncolour = [1.0, 1.0, 1.0]\n",
+		)
+		.unwrap();
+		let [HittableObject::Sphere(a), HittableObject::Sphere(b)] = &scene.objects[..] else {
+			panic!("expected two spheres");
+		};
+		assert!(Arc::ptr_eq(&a.material, &b.material));
+	}
+
+	#[test]
+	fn cycle() {
+		let message = load_error(
+			&format!("{SPHERE}material = {{ texture = \"a\" }}\n"),
+			"[textures.a]\ntype = \"CheckerTexture\"\nscale = 1.0\neven = \"b\"\nodd = \"b\"\n\
+			[textures.b]\ntype = \"CheckerTexture\"\nscale = 1.0\neven = \"a\"\nodd = \"a\"\n",
+		);
+		assert!(
+			message.contains("texture `a` refers to itself"),
+			"{message}"
+		);
+	}
+
+	#[test]
+	fn unknown_name() {
+		let message = load_error(&format!("{SPHERE}material = \"missing\"\n"), "");
+		assert!(message.contains("unknown material `missing`"), "{message}");
+	}
+
+	#[test]
+	fn unused_items_are_checked() {
+		let message = load_error(
+			&format!("{SPHERE}material = \"diffuse\"\n"),
+			"[materials.unused]\ntexture = \"missing\"\n",
+		);
+		assert!(message.contains("in material `unused`"), "{message}");
+		assert!(message.contains("unknown texture `missing`"), "{message}");
+	}
+}
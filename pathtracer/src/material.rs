@@ -1,17 +1,25 @@
+use std::sync::Arc;
+
 use euclid::default::Vector3D;
-use serde::Deserialize;
+use serde::{
+	de::{self, IntoDeserializer},
+	Deserialize, Serialize,
+};
 
 use crate::{
 	hittable::Hit,
+	library,
+	medium::AnyMedium,
 	ray::Ray,
-	texture::{AnyTexture, Texture},
+	texture::{AnyTexture, SolidColour},
 	util,
 };
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(try_from = "SerializedMaterial", into = "SerializedMaterial")]
 pub struct Material {
 	/// The base (albedo) texture of this material.
-	pub texture: AnyTexture,
+	pub texture: Arc<AnyTexture>,
 
 	/// The amount of light this texture emits.
 	pub emission: f32,
@@ -40,86 +48,147 @@ pub struct Material {
 
 	/// The index of refraction for transmission.
 	pub ior: f32,
+
+	/// How much light is absorbed per unit of distance travelled inside the
+	/// object, for each colour channel. Thicker parts of transparent objects
+	/// will appear darker and more saturated.
+	pub absorption: Vector3D<f32>,
+
+	/// The participating medium that fills the inside of the object. Only
+	/// makes sense for closed objects.
+	pub medium: Option<AnyMedium>,
+}
+
+impl Default for Material {
+	fn default() -> Self {
+		Material::diffuse(SolidColour::new(0.8, 0.8, 0.8).into())
+	}
 }
 
 impl Material {
 	pub fn metal(texture: AnyTexture, roughness: f32) -> Material {
 		Material {
-			texture,
+			texture: Arc::new(texture),
 			emission: 0.0,
 			metallic: 1.0,
 			specular: 0.0,
 			transparency: 0.0,
 			roughness,
 			ior: 1.5,
+			absorption: Vector3D::zero(),
+			medium: None,
 		}
 	}
 
 	pub fn dielectric(texture: AnyTexture, roughness: f32) -> Material {
 		Material {
-			texture,
+			texture: Arc::new(texture),
 			emission: 0.0,
 			metallic: 0.0,
 			specular: 1.0,
 			transparency: 0.0,
 			roughness,
 			ior: 1.5,
+			absorption: Vector3D::zero(),
+			medium: None,
 		}
 	}
 
 	pub fn diffuse(texture: AnyTexture) -> Material {
 		Material {
-			texture,
+			texture: Arc::new(texture),
 			metallic: 0.0,
 			specular: 0.0,
 			roughness: 0.0,
 			emission: 0.0,
 			transparency: 0.0,
 			ior: 1.5,
+			absorption: Vector3D::zero(),
+			medium: None,
 		}
 	}
 
 	pub fn transparent(texture: AnyTexture, roughness: f32, ior: f32) -> Material {
 		Material {
-			texture,
+			texture: Arc::new(texture),
 			emission: 0.0,
 			metallic: 0.0,
 			specular: 1.0,
 			transparency: 1.0,
 			roughness,
 			ior,
+			absorption: Vector3D::zero(),
+			medium: None,
 		}
 	}
 
+	/// An invisible boundary around a participating medium, for smoke or fog
+	/// contained in an object.
+	pub fn volume(medium: AnyMedium) -> Material {
+		Material {
+			texture: Arc::new(SolidColour::new(1.0, 1.0, 1.0).into()),
+			emission: 0.0,
+			metallic: 0.0,
+			specular: 0.0,
+			transparency: 1.0,
+			roughness: 0.0,
+			ior: 1.0,
+			absorption: Vector3D::zero(),
+			medium: Some(medium),
+		}
+	}
+
+	/// Returns the preset called `name` with a light grey texture, like
+	/// `"diffuse"` for [`Material::diffuse`].
+	pub(crate) fn preset(name: &str) -> Option<Material> {
+		let name: de::value::StrDeserializer<de::value::Error> = name.into_deserializer();
+		Preset::deserialize(name).ok()?.material(None).ok()
+	}
+
 	pub fn emissive(texture: AnyTexture) -> Material {
 		Material {
-			texture,
+			texture: Arc::new(texture),
 			emission: 1.0,
 			metallic: 0.0,
 			specular: 0.0,
 			transparency: 0.0,
 			roughness: 0.0,
 			ior: 1.5,
+			absorption: Vector3D::zero(),
+			medium: None,
 		}
 	}
 }
 
 pub fn bounce(ray: &Ray, hit: &Hit) -> (Option<Ray>, Vector3D<f32>) {
-	if rand::random::<f32>() < hit.material.emission {
+	if util::random::<f32>() < hit.material.emission {
 		emissive(ray, hit)
-	} else if rand::random::<f32>() < hit.material.metallic {
+	} else if util::random::<f32>() < hit.material.metallic {
 		metallic(ray, hit)
-	} else if rand::random::<f32>() < hit.material.specular * schlick(ray, hit) {
+	} else if util::random::<f32>() < hit.material.specular * schlick(ray, hit) {
 		specular(ray, hit)
-	} else if rand::random::<f32>() < hit.material.transparency {
+	} else if util::random::<f32>() < hit.material.transparency {
 		refract(ray, hit)
 	} else {
 		diffuse(ray, hit)
 	}
 }
 
+/// Returns the fraction of light that is left after travelling `distance`
+/// through the inside of an object with the `interior` material, following
+/// the Beer-Lambert law.
+pub fn absorb(interior: Option<&Material>, distance: f32) -> Vector3D<f32> {
+	match interior {
+		Some(material) => {
+			let absorption = material.absorption * -distance;
+			Vector3D::new(absorption.x.exp(), absorption.y.exp(), absorption.z.exp())
+		}
+		None => Vector3D::one(),
+	}
+}
+
 fn emissive(_ray: &Ray, hit: &Hit) -> (Option<Ray>, Vector3D<f32>) {
-	(None, hit.material.texture.colour(hit.uv, hit.point))
+	(None, hit.material.texture.colour_at(hit))
 }
 
 fn metallic(ray: &Ray, hit: &Hit) -> (Option<Ray>, Vector3D<f32>) {
@@ -127,12 +196,10 @@ fn metallic(ray: &Ray, hit: &Hit) -> (Option<Ray>, Vector3D<f32>) {
 	let new_ray = Ray::new(
 		hit.point,
 		reflected_dir + util::random_in_unit_sphere() * hit.material.roughness,
+		ray.time,
 	);
 	if new_ray.dir.dot(hit.normal) > 0.0 {
-		(
-			Some(new_ray),
-			hit.material.texture.colour(hit.uv, hit.point),
-		)
+		(Some(new_ray), hit.material.texture.colour_at(hit))
 	} else {
 		(None, Vector3D::zero())
 	}
@@ -143,6 +210,7 @@ fn specular(ray: &Ray, hit: &Hit) -> (Option<Ray>, Vector3D<f32>) {
 	let new_ray = Ray::new(
 		hit.point,
 		reflected_dir + util::random_in_unit_sphere() * hit.material.roughness,
+		ray.time,
 	);
 	if new_ray.dir.dot(hit.normal) > 0.0 {
 		(Some(new_ray), Vector3D::one())
@@ -151,11 +219,11 @@ fn specular(ray: &Ray, hit: &Hit) -> (Option<Ray>, Vector3D<f32>) {
 	}
 }
 
-fn diffuse(_ray: &Ray, hit: &Hit) -> (Option<Ray>, Vector3D<f32>) {
+fn diffuse(ray: &Ray, hit: &Hit) -> (Option<Ray>, Vector3D<f32>) {
 	let scattered_dir = hit.normal + util::random_unit_vector();
 	(
-		Some(Ray::new(hit.point, scattered_dir)),
-		hit.material.texture.colour(hit.uv, hit.point),
+		Some(Ray::new(hit.point, scattered_dir, ray.time)),
+		hit.material.texture.colour_at(hit),
 	)
 }
 
@@ -180,11 +248,9 @@ fn refract(ray: &Ray, hit: &Hit) -> (Option<Ray>, Vector3D<f32>) {
 	let new_ray = Ray::new(
 		hit.point,
 		refracted_dir + util::random_in_unit_sphere() * hit.material.roughness,
+		ray.time,
 	);
-	(
-		Some(new_ray),
-		hit.material.texture.colour(hit.uv, hit.point),
-	)
+	(Some(new_ray), hit.material.texture.colour_at(hit))
 }
 
 fn schlick(ray: &Ray, hit: &Hit) -> f32 {
@@ -199,3 +265,127 @@ fn schlick(ray: &Ray, hit: &Hit) -> f32 {
 	let r0 = ((1.0 - ior) / (1.0 + ior)).powi(2);
 	r0 + (1.0 - r0) * (1.0 - cos_theta).powi(5)
 }
+
+/// The presets that a material in a scene file can start from, matching the
+/// constructors of [`Material`].
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Preset {
+	#[default]
+	Diffuse,
+	Metal,
+	Dielectric,
+	Transparent,
+	Emissive,
+	Volume,
+}
+
+impl Preset {
+	fn material(self, medium: Option<AnyMedium>) -> Result<Material, String> {
+		let texture: AnyTexture = SolidColour::new(0.8, 0.8, 0.8).into();
+		Ok(match self {
+			Preset::Diffuse => Material::diffuse(texture),
+			Preset::Metal => Material::metal(texture, 0.0),
+			Preset::Dielectric => Material::dielectric(texture, 0.0),
+			Preset::Transparent => Material::transparent(texture, 0.0, 1.5),
+			Preset::Emissive => Material::emissive(texture),
+			Preset::Volume => Material::volume(medium.ok_or("a volume material needs a medium")?),
+		})
+	}
+}
+
+/// A material in a scene file. Every field is optional and overrides the
+/// value of the preset, which is `diffuse` by default. Materials are
+/// serialized with every field set, so the preset is left out.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct SerializedMaterial {
+	#[serde(default, skip_serializing)]
+	preset: Preset,
+	#[serde(
+		default,
+		deserialize_with = "deserialize_texture",
+		skip_serializing_if = "Option::is_none"
+	)]
+	texture: Option<Arc<AnyTexture>>,
+
+	/// Shorthand for a [`SolidColour`] texture.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	colour: Option<Vector3D<f32>>,
+	emission: Option<f32>,
+	metallic: Option<f32>,
+	specular: Option<f32>,
+	transparency: Option<f32>,
+	roughness: Option<f32>,
+	ior: Option<f32>,
+	absorption: Option<Vector3D<f32>>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	medium: Option<AnyMedium>,
+}
+
+fn deserialize_texture<'de, D: de::Deserializer<'de>>(
+	deserializer: D,
+) -> Result<Option<Arc<AnyTexture>>, D::Error> {
+	library::deserialize(deserializer).map(Some)
+}
+
+impl TryFrom<SerializedMaterial> for Material {
+	type Error = String;
+
+	fn try_from(value: SerializedMaterial) -> Result<Self, Self::Error> {
+		let mut material = value.preset.material(value.medium.clone())?;
+		match (value.texture, value.colour) {
+			(Some(_), Some(_)) => {
+				return Err("a material cannot have both a texture and a colour".to_string())
+			}
+			(Some(texture), None) => material.texture = texture,
+			(None, Some(colour)) => {
+				material.texture = Arc::new(SolidColour::new(colour.x, colour.y, colour.z).into())
+			}
+			(None, None) => {}
+		}
+
+		let overrides = [
+			(&mut material.emission, value.emission),
+			(&mut material.metallic, value.metallic),
+			(&mut material.specular, value.specular),
+			(&mut material.transparency, value.transparency),
+			(&mut material.roughness, value.roughness),
+			(&mut material.ior, value.ior),
+		];
+		for (field, value) in overrides {
+			if let Some(value) = value {
+				*field = value;
+			}
+		}
+		if let Some(absorption) = value.absorption {
+			material.absorption = absorption;
+		}
+		if value.medium.is_some() {
+			material.medium = value.medium;
+		}
+		Ok(material)
+	}
+}
+
+impl From<Material> for SerializedMaterial {
+	fn from(value: Material) -> Self {
+		let (texture, colour) = match value.texture.as_ref() {
+			AnyTexture::SolidColour(texture) => (None, Some(texture.colour)),
+			_ => (Some(value.texture), None),
+		};
+		Self {
+			preset: Preset::Diffuse,
+			texture,
+			colour,
+			emission: Some(value.emission),
+			metallic: Some(value.metallic),
+			specular: Some(value.specular),
+			transparency: Some(value.transparency),
+			roughness: Some(value.roughness),
+			ior: Some(value.ior),
+			absorption: Some(value.absorption),
+			medium: value.medium,
+		}
+	}
+}
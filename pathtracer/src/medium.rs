@@ -0,0 +1,102 @@
+use core::f32::consts::PI;
+
+use euclid::default::Vector3D;
+use serde::{Deserialize, Serialize};
+
+use crate::{ray::Ray, util};
+
+pub trait Medium {
+	/// Samples a free-flight distance along `ray`. If the ray scatters within
+	/// the medium before reaching `max_distance`, returns the scattered ray and
+	/// the colour it should be multiplied by. Returns [`None`] if the ray
+	/// passes through the medium unaffected.
+	fn scatter(&self, ray: &Ray, max_distance: f32) -> Option<(Ray, Vector3D<f32>)>;
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type")]
+pub enum AnyMedium {
+	HomogeneousMedium(HomogeneousMedium),
+}
+
+impl Medium for AnyMedium {
+	fn scatter(&self, ray: &Ray, max_distance: f32) -> Option<(Ray, Vector3D<f32>)> {
+		match self {
+			AnyMedium::HomogeneousMedium(m) => m.scatter(ray, max_distance),
+		}
+	}
+}
+
+/// A medium with the same density everywhere, like fog or smoke.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HomogeneousMedium {
+	/// The amount of light absorbed per unit of distance.
+	absorption: f32,
+
+	/// The amount of light scattered per unit of distance.
+	scattering: f32,
+
+	/// The colour of the scattered light.
+	colour: Vector3D<f32>,
+
+	/// The Henyey-Greenstein asymmetry parameter, between `-1.0` and `1.0`.
+	/// Positive values scatter light mostly forward, negative values mostly
+	/// backward and `0.0` scatters light equally in all directions.
+	#[serde(default)]
+	anisotropy: f32,
+}
+
+impl HomogeneousMedium {
+	pub fn new(absorption: f32, scattering: f32, colour: Vector3D<f32>, anisotropy: f32) -> Self {
+		Self {
+			absorption,
+			scattering,
+			colour,
+			anisotropy,
+		}
+	}
+}
+
+impl From<HomogeneousMedium> for AnyMedium {
+	fn from(value: HomogeneousMedium) -> Self {
+		AnyMedium::HomogeneousMedium(value)
+	}
+}
+
+impl Medium for HomogeneousMedium {
+	fn scatter(&self, ray: &Ray, max_distance: f32) -> Option<(Ray, Vector3D<f32>)> {
+		let extinction = self.absorption + self.scattering;
+		if extinction <= 0.0 {
+			return None;
+		}
+
+		let distance = -(1.0 - util::random::<f32>()).ln() / extinction;
+		if distance >= max_distance {
+			return None;
+		}
+
+		let dir = henyey_greenstein(ray.dir, self.anisotropy);
+		let albedo = self.scattering / extinction;
+		Some((
+			Ray::new(ray.at(distance), dir, ray.time),
+			self.colour * albedo,
+		))
+	}
+}
+
+/// Samples a new direction around `dir` from the Henyey-Greenstein phase
+/// function with asymmetry parameter `g`.
+fn henyey_greenstein(dir: Vector3D<f32>, g: f32) -> Vector3D<f32> {
+	let xi = util::random::<f32>();
+	let cos_theta = if g.abs() < 1e-3 {
+		1.0 - 2.0 * xi
+	} else {
+		let sq = (1.0 - g * g) / (1.0 - g + 2.0 * g * xi);
+		(1.0 + g * g - sq * sq) / (2.0 * g)
+	};
+	let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+	let phi = 2.0 * PI * util::random::<f32>();
+
+	let (u, v) = util::orthonormal_basis(dir);
+	u * (sin_theta * phi.cos()) + v * (sin_theta * phi.sin()) + dir * cos_theta
+}
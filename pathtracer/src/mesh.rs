@@ -0,0 +1,464 @@
+use std::{fs, path::Path, str::SplitAsciiWhitespace};
+
+use euclid::default::{Point3D, Vector3D};
+
+use crate::Error;
+
+/// The vertices and triangles read from a mesh file.
+pub(crate) struct MeshData {
+	pub positions: Vec<Point3D<f32>>,
+
+	/// The colour of every vertex, if the file has vertex colours.
+	pub colours: Option<Vec<Vector3D<f32>>>,
+
+	/// The indices of the vertices of every triangle.
+	pub faces: Vec<[usize; 3]>,
+}
+
+/// Loads an ASCII or binary PLY or STL file, depending on the extension of
+/// `path`. Polygons with more than three vertices are split into triangles.
+pub(crate) fn load(path: &Path) -> Result<MeshData, Error> {
+	let data = fs::read(path).map_err(|source| Error::Io {
+		path: path.to_path_buf(),
+		source,
+	})?;
+	let extension = path
+		.extension()
+		.map(|extension| extension.to_ascii_lowercase());
+	let mesh = match extension.as_ref().and_then(|extension| extension.to_str()) {
+		Some("ply") => load_ply(&data),
+		Some("stl") => load_stl(&data),
+		_ => Err("unsupported format, expected a .ply or .stl file".to_string()),
+	};
+
+	mesh.map_err(|message| Error::Mesh {
+		path: path.to_path_buf(),
+		message,
+	})
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PlyType {
+	I8,
+	U8,
+	I16,
+	U16,
+	I32,
+	U32,
+	F32,
+	F64,
+}
+
+impl PlyType {
+	fn parse(name: &str) -> Result<Self, String> {
+		Ok(match name {
+			"char" | "int8" => PlyType::I8,
+			"uchar" | "uint8" => PlyType::U8,
+			"short" | "int16" => PlyType::I16,
+			"ushort" | "uint16" => PlyType::U16,
+			"int" | "int32" => PlyType::I32,
+			"uint" | "uint32" => PlyType::U32,
+			"float" | "float32" => PlyType::F32,
+			"double" | "float64" => PlyType::F64,
+			_ => return Err(format!("unknown property type `{name}`")),
+		})
+	}
+
+	fn size(self) -> usize {
+		match self {
+			PlyType::I8 | PlyType::U8 => 1,
+			PlyType::I16 | PlyType::U16 => 2,
+			PlyType::I32 | PlyType::U32 | PlyType::F32 => 4,
+			PlyType::F64 => 8,
+		}
+	}
+}
+
+struct PlyProperty {
+	name: String,
+
+	/// The type of the number of items, for list properties.
+	count_type: Option<PlyType>,
+	value_type: PlyType,
+}
+
+struct PlyElement {
+	name: String,
+	count: usize,
+	properties: Vec<PlyProperty>,
+}
+
+/// Reads the numbers in the body of a PLY file.
+enum PlyReader<'a> {
+	Ascii(SplitAsciiWhitespace<'a>),
+	Binary { data: &'a [u8], big_endian: bool },
+}
+
+impl PlyReader<'_> {
+	fn read(&mut self, value_type: PlyType) -> Result<f64, String> {
+		match self {
+			PlyReader::Ascii(tokens) => {
+				let token = tokens.next().ok_or("unexpected end of file")?;
+				token
+					.parse()
+					.map_err(|_| format!("`{token}` is not a number"))
+			}
+			PlyReader::Binary { data, big_endian } => {
+				let size = value_type.size();
+				if data.len() < size {
+					return Err("unexpected end of file".to_string());
+				}
+				let (bytes, rest) = data.split_at(size);
+				*data = rest;
+
+				let mut buf = [0; 8];
+				buf[..size].copy_from_slice(bytes);
+				if *big_endian {
+					buf[..size].reverse();
+				}
+				let [b0, b1, b2, b3, ..] = buf;
+				Ok(match value_type {
+					PlyType::I8 => b0 as i8 as f64,
+					PlyType::U8 => b0 as f64,
+					PlyType::I16 => i16::from_le_bytes([b0, b1]) as f64,
+					PlyType::U16 => u16::from_le_bytes([b0, b1]) as f64,
+					PlyType::I32 => i32::from_le_bytes([b0, b1, b2, b3]) as f64,
+					PlyType::U32 => u32::from_le_bytes([b0, b1, b2, b3]) as f64,
+					PlyType::F32 => f32::from_le_bytes([b0, b1, b2, b3]) as f64,
+					PlyType::F64 => f64::from_le_bytes(buf),
+				})
+			}
+		}
+	}
+}
+
+// http://paulbourke.net/dataformats/ply/
+fn load_ply(data: &[u8]) -> Result<MeshData, String> {
+	const END_HEADER: &[u8] = b"end_header";
+	let header_end = data
+		.windows(END_HEADER.len())
+		.position(|window| window == END_HEADER)
+		.ok_or("missing end_header")?;
+	let header = std::str::from_utf8(&data[..header_end]).map_err(|_| "invalid header")?;
+	let body_start = data[header_end..]
+		.iter()
+		.position(|&b| b == b'\n')
+		.map_or(data.len(), |i| header_end + i + 1);
+	let body = &data[body_start..];
+
+	let mut lines = header.lines().map(str::trim);
+	if lines.next() != Some("ply") {
+		return Err("not a PLY file".to_string());
+	}
+	let mut reader = None;
+	let mut elements: Vec<PlyElement> = Vec::new();
+	for line in lines {
+		let words: Vec<_> = line.split_ascii_whitespace().collect();
+		match words.as_slice() {
+			["format", "ascii", _] => {
+				let body = std::str::from_utf8(body).map_err(|_| "invalid ASCII data")?;
+				reader = Some(PlyReader::Ascii(body.split_ascii_whitespace()));
+			}
+			["format", format @ ("binary_little_endian" | "binary_big_endian"), _] => {
+				reader = Some(PlyReader::Binary {
+					data: body,
+					big_endian: *format == "binary_big_endian",
+				});
+			}
+			["format", format, ..] => return Err(format!("unknown format `{format}`")),
+			["element", name, count] => elements.push(PlyElement {
+				name: name.to_string(),
+				count: count
+					.parse()
+					.map_err(|_| format!("invalid element count `{count}`"))?,
+				properties: Vec::new(),
+			}),
+			["property", "list", count_type, value_type, name] => elements
+				.last_mut()
+				.ok_or("property before element")?
+				.properties
+				.push(PlyProperty {
+					name: name.to_string(),
+					count_type: Some(PlyType::parse(count_type)?),
+					value_type: PlyType::parse(value_type)?,
+				}),
+			["property", value_type, name] => elements
+				.last_mut()
+				.ok_or("property before element")?
+				.properties
+				.push(PlyProperty {
+					name: name.to_string(),
+					count_type: None,
+					value_type: PlyType::parse(value_type)?,
+				}),
+			["comment" | "obj_info", ..] | [] => {}
+			_ => return Err(format!("invalid header line `{line}`")),
+		}
+	}
+	let mut reader = reader.ok_or("missing format")?;
+
+	let mut mesh = MeshData {
+		positions: Vec::new(),
+		colours: None,
+		faces: Vec::new(),
+	};
+	for element in &elements {
+		let index = |name: &str| element.properties.iter().position(|p| p.name == name);
+		let (x, y, z) = (index("x"), index("y"), index("z"));
+		let (red, green, blue) = (index("red"), index("green"), index("blue"));
+		let face_indices = index("vertex_indices").or_else(|| index("vertex_index"));
+
+		let has_colours =
+			element.name == "vertex" && red.is_some() && green.is_some() && blue.is_some();
+		if has_colours {
+			// Every vertex takes at least one byte, so a corrupt count can't
+			// make this allocate more than the size of the file
+			mesh.colours = Some(Vec::with_capacity(element.count.min(body.len())));
+		}
+
+		let mut values = Vec::new();
+		for _ in 0..element.count {
+			values.clear();
+			let mut list = Vec::new();
+			for (i, property) in element.properties.iter().enumerate() {
+				match property.count_type {
+					Some(count_type) => {
+						let count = reader.read(count_type)? as usize;
+						let mut items = Vec::new();
+						for _ in 0..count {
+							items.push(reader.read(property.value_type)?);
+						}
+						if Some(i) == face_indices {
+							list = items;
+						}
+						values.push(0.0);
+					}
+					None => values.push(reader.read(property.value_type)?),
+				}
+			}
+
+			match element.name.as_str() {
+				"vertex" => {
+					let (Some(x), Some(y), Some(z)) = (x, y, z) else {
+						return Err("vertices need x, y and z".to_string());
+					};
+					mesh.positions
+						.push(Point3D::new(values[x], values[y], values[z]).cast());
+					if let (Some(colours), Some(r), Some(g), Some(b)) =
+						(&mut mesh.colours, red, green, blue)
+					{
+						let colour = Vector3D::new(values[r], values[g], values[b]).cast();
+						// Integer colours go from 0 to 255
+						colours.push(match element.properties[r].value_type {
+							PlyType::F32 | PlyType::F64 => colour,
+							_ => colour / 255.0,
+						});
+					}
+				}
+				"face" => {
+					let indices = list
+						.iter()
+						.map(|&i| {
+							if i >= 0.0 && i.fract() == 0.0 {
+								Ok(i as usize)
+							} else {
+								Err(format!("invalid vertex index `{i}`"))
+							}
+						})
+						.collect::<Result<Vec<_>, _>>()?;
+					mesh.faces.extend(fan(&indices));
+				}
+				_ => {}
+			}
+		}
+	}
+
+	let n_vertices = mesh.positions.len();
+	match mesh.faces.iter().flatten().find(|&&i| i >= n_vertices) {
+		Some(i) => Err(format!("vertex {i} does not exist")),
+		None => Ok(mesh),
+	}
+}
+
+/// Splits a polygon into triangles that share its first vertex.
+fn fan(indices: &[usize]) -> impl Iterator<Item = [usize; 3]> + '_ {
+	indices
+		.windows(2)
+		.skip(1)
+		.map(|pair| [indices[0], pair[0], pair[1]])
+}
+
+// https://en.wikipedia.org/wiki/STL_(file_format)
+fn load_stl(data: &[u8]) -> Result<MeshData, String> {
+	let mut positions = Vec::new();
+
+	// Binary files can start with "solid" as well, so check if the size
+	// matches the triangle count first.
+	let binary_count = data
+		.get(80..84)
+		.map(|count| u32::from_le_bytes([count[0], count[1], count[2], count[3]]) as usize);
+	match binary_count {
+		Some(count) if data.len() == 84 + 50 * count => {
+			for triangle in data[84..].chunks_exact(50) {
+				// Skip the normal, and the attributes at the end
+				for vertex in triangle[12..48].chunks_exact(12) {
+					let [x, y, z] = [0, 4, 8].map(|i| {
+						f32::from_le_bytes([vertex[i], vertex[i + 1], vertex[i + 2], vertex[i + 3]])
+					});
+					positions.push(Point3D::new(x, y, z));
+				}
+			}
+		}
+		_ if data.starts_with(b"solid") => {
+			let text = std::str::from_utf8(data).map_err(|_| "invalid ASCII data")?;
+			let mut tokens = text.split_ascii_whitespace();
+			while let Some(token) = tokens.next() {
+				if token == "vertex" {
+					let mut coordinate = || -> Result<f32, String> {
+						let token = tokens.next().ok_or("unexpected end of file")?;
+						token
+							.parse()
+							.map_err(|_| format!("`{token}` is not a number"))
+					};
+					positions.push(Point3D::new(coordinate()?, coordinate()?, coordinate()?));
+				}
+			}
+			if positions.len() % 3 != 0 {
+				return Err("incomplete triangle".to_string());
+			}
+		}
+		_ => return Err("not an STL file".to_string()),
+	}
+
+	let faces = (0..positions.len() / 3)
+		.map(|i| [3 * i, 3 * i + 1, 3 * i + 2])
+		.collect();
+	Ok(MeshData {
+		positions,
+		colours: None,
+		faces,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const QUAD_POSITIONS: [[f32; 3]; 4] = [
+		[0.0, 0.0, 0.0],
+		[1.0, 0.0, 0.0],
+		[1.0, 1.0, 0.0],
+		[0.0, 1.0, 0.0],
+	];
+
+	/// A binary PLY file with a single quad.
+	fn binary_quad(
+		format: &str,
+		to_bytes: fn(f32) -> [u8; 4],
+		index: fn(i32) -> [u8; 4],
+	) -> Vec<u8> {
+		let mut data = format!(
+			"ply\nformat {format} 1.0\nelement vertex 4\nproperty float x\nproperty float y\n\
+			property float z\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n"
+		)
+		.into_bytes();
+		for position in QUAD_POSITIONS {
+			data.extend(position.into_iter().flat_map(to_bytes));
+		}
+		data.push(4);
+		data.extend((0..4).flat_map(index));
+		data
+	}
+
+	fn assert_quad(mesh: &MeshData) {
+		let positions: Vec<_> = QUAD_POSITIONS
+			.iter()
+			.map(|&[x, y, z]| Point3D::new(x, y, z))
+			.collect();
+		assert_eq!(mesh.positions, positions);
+		assert_eq!(mesh.faces, [[0, 1, 2], [0, 2, 3]]);
+	}
+
+	#[test]
+	fn ascii_quad_is_split_into_a_fan() {
+		let data = b"ply\nformat ascii 1.0\ncomment a quad\nelement vertex 4\n\
+			property float x\nproperty float y\nproperty float z\nelement face 1\n\
+			property list uchar int vertex_indices\nend_header\n\
+			0 0 0\n1 0 0\n1 1 0\n0 1 0\n4 0 1 2 3\n";
+		let mesh = load_ply(data).unwrap();
+		assert_quad(&mesh);
+		assert!(mesh.colours.is_none());
+	}
+
+	#[test]
+	fn binary_little_endian() {
+		let data = binary_quad("binary_little_endian", f32::to_le_bytes, i32::to_le_bytes);
+		assert_quad(&load_ply(&data).unwrap());
+	}
+
+	#[test]
+	fn binary_big_endian() {
+		let data = binary_quad("binary_big_endian", f32::to_be_bytes, i32::to_be_bytes);
+		assert_quad(&load_ply(&data).unwrap());
+	}
+
+	#[test]
+	fn vertex_colours() {
+		let data = b"ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\n\
+			property float y\nproperty float z\nproperty uchar red\nproperty uchar green\n\
+			property uchar blue\nelement face 1\nproperty list uchar uint vertex_indices\n\
+			end_header\n0 0 0 255 0 0\n1 0 0 0 255 0\n0 1 0 0 0 51\n3 0 1 2\n";
+		let mesh = load_ply(data).unwrap();
+		let colours = [
+			Vector3D::new(1.0, 0.0, 0.0),
+			Vector3D::new(0.0, 1.0, 0.0),
+			Vector3D::new(0.0, 0.0, 0.2),
+		];
+		assert_eq!(mesh.colours.unwrap(), colours);
+		assert_eq!(mesh.faces, [[0, 1, 2]]);
+	}
+
+	#[test]
+	fn truncated_file() {
+		let mut data = binary_quad("binary_little_endian", f32::to_le_bytes, i32::to_le_bytes);
+		data.truncate(data.len() - 3);
+		assert_eq!(load_ply(&data).err().unwrap(), "unexpected end of file");
+	}
+
+	#[test]
+	fn huge_count_fails_without_allocating() {
+		let data = b"ply\nformat binary_little_endian 1.0\nelement vertex 4000000000\n\
+			property float x\nproperty float y\nproperty float z\nproperty uchar red\n\
+			property uchar green\nproperty uchar blue\nelement face 1\n\
+			property list uint int vertex_indices\nend_header\n";
+		assert_eq!(load_ply(data).err().unwrap(), "unexpected end of file");
+	}
+
+	#[test]
+	fn invalid_indices() {
+		let data = |indices: &str| {
+			format!(
+				"ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\n\
+				property float z\nelement face 1\nproperty list uchar int vertex_indices\n\
+				 end_header\n0 0 0\n1 0 0\n0 1 0\n3 {indices}\n"
+			)
+		};
+		let error = |indices| load_ply(data(indices).as_bytes()).err().unwrap();
+		assert_eq!(error("0 -1 2"), "invalid vertex index `-1`");
+		assert_eq!(error("0 1 3"), "vertex 3 does not exist");
+	}
+
+	#[test]
+	fn binary_stl() {
+		let mut data = vec![0; 80];
+		data.extend(1u32.to_le_bytes());
+		data.extend([0; 12]);
+		for position in &QUAD_POSITIONS[..3] {
+			data.extend(position.iter().flat_map(|v| v.to_le_bytes()));
+		}
+		data.extend([0; 2]);
+		let mesh = load_stl(&data).unwrap();
+		assert_eq!(mesh.positions[1], Point3D::new(1.0, 0.0, 0.0));
+		assert_eq!(mesh.faces, [[0, 1, 2]]);
+	}
+}
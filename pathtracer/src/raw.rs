@@ -0,0 +1,205 @@
+use std::{
+	fs::File,
+	io::{self, BufReader, BufWriter, Read, Write},
+	path::Path,
+};
+
+use crate::{
+	checkpoint::Checkpoint,
+	util::{read_array, read_f32, read_scene_hash, read_u32, read_u64, write_scene_hash},
+};
+
+const MAGIC: &[u8; 4] = b"PTRW";
+const VERSION: u32 = 3;
+
+/// An unprocessed render, storing the average linear colour of every pixel
+/// together with the number of samples it was rendered with. Renders of the
+/// same scene with different seeds, or of different regions of it, can be
+/// merged into a less noisy or more complete image.
+#[derive(Debug, Clone)]
+pub struct RawImage {
+	pub width: u32,
+	pub height: u32,
+
+	/// The maximum number of bounces of each ray.
+	pub max_bounces: u32,
+
+	/// The [`scene_hash`](crate::checkpoint::scene_hash) of the rendered scene.
+	pub scene_hash: Option<u64>,
+
+	/// The highest number of samples of any pixel.
+	pub samples: u32,
+
+	/// The number of samples of every pixel, which is zero for pixels outside
+	/// the rendered region.
+	pub pixel_samples: Box<[u32]>,
+
+	/// The seeds of all renders that make up this image.
+	pub seeds: Vec<u64>,
+
+	/// The RGB colour of every pixel.
+	pub pixels: Box<[f32]>,
+}
+
+impl RawImage {
+	/// Creates an image from the pixels accumulated in a checkpoint. Pixels
+	/// outside of its region have no samples.
+	pub fn from_checkpoint(checkpoint: &Checkpoint) -> Self {
+		let (width, samples) = (checkpoint.width, checkpoint.samples);
+		let scale = 255.0 * samples.max(1) as f32;
+		let pixel_samples = (0..width * checkpoint.height)
+			.map(|idx| match checkpoint.region {
+				Some(region) if !region.contains(idx % width, idx / width) => 0,
+				_ => samples,
+			})
+			.collect();
+		Self {
+			width,
+			height: checkpoint.height,
+			max_bounces: checkpoint.max_bounces,
+			scene_hash: checkpoint.scene_hash,
+			samples,
+			pixel_samples,
+			seeds: vec![checkpoint.seed],
+			pixels: checkpoint
+				.pixels
+				.chunks_exact(4)
+				.flat_map(|pixel| &pixel[..3])
+				.map(|&subpixel| subpixel as f32 / scale)
+				.collect(),
+		}
+	}
+
+	/// Combines several renders of the same scene with the same settings into
+	/// one, weighting every pixel of each render by its number of samples.
+	pub fn merge(images: &[RawImage]) -> Result<Self, String> {
+		let Some(first) = images.first() else {
+			return Err("no images to merge".to_string());
+		};
+		let mut merged = Self {
+			width: first.width,
+			height: first.height,
+			max_bounces: first.max_bounces,
+			scene_hash: first.scene_hash,
+			samples: 0,
+			pixel_samples: vec![0; first.pixel_samples.len()].into_boxed_slice(),
+			seeds: Vec::new(),
+			pixels: vec![0.0; first.pixels.len()].into_boxed_slice(),
+		};
+
+		for image in images {
+			if (image.width, image.height) != (merged.width, merged.height) {
+				return Err(format!(
+					"cannot merge a {}x{} image with a {}x{} image",
+					image.width, image.height, merged.width, merged.height
+				));
+			}
+			if image.max_bounces != merged.max_bounces {
+				return Err(format!(
+					"cannot merge a render with {} bounces with one with {} bounces",
+					image.max_bounces, merged.max_bounces
+				));
+			}
+			if image.scene_hash != merged.scene_hash {
+				return Err("cannot merge renders of different scenes".to_string());
+			}
+			if let Some(seed) = image.seeds.iter().find(|s| merged.seeds.contains(s)) {
+				return Err(format!("seed {seed} occurs in multiple images"));
+			}
+			let pixels = merged
+				.pixels
+				.chunks_exact_mut(3)
+				.zip(image.pixels.chunks_exact(3));
+			for ((sum, value), samples) in pixels.zip(image.pixel_samples.iter()) {
+				for (sum, value) in sum.iter_mut().zip(value) {
+					*sum += value * *samples as f32;
+				}
+			}
+			for (sum, samples) in merged
+				.pixel_samples
+				.iter_mut()
+				.zip(image.pixel_samples.iter())
+			{
+				*sum += samples;
+			}
+			merged.seeds.extend(&image.seeds);
+		}
+
+		let pixels = merged.pixels.chunks_exact_mut(3);
+		for (pixel, &samples) in pixels.zip(merged.pixel_samples.iter()) {
+			pixel
+				.iter_mut()
+				.for_each(|value| *value /= samples.max(1) as f32);
+		}
+		merged.samples = merged.pixel_samples.iter().copied().max().unwrap_or(0);
+		Ok(merged)
+	}
+
+	/// Converts the image to gamma-corrected 8-bit RGBA. Pixels without any
+	/// samples are transparent.
+	pub fn to_canvas(&self) -> Vec<u8> {
+		self.pixels
+			.chunks_exact(3)
+			.zip(self.pixel_samples.iter())
+			.flat_map(|(pixel, &samples)| {
+				let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(|v| (v.sqrt() * 255.0) as u8);
+				[r, g, b, if samples > 0 { 255 } else { 0 }]
+			})
+			.collect()
+	}
+
+	pub fn save(&self, path: &Path) -> io::Result<()> {
+		let mut writer = BufWriter::new(File::create(path)?);
+		writer.write_all(MAGIC)?;
+		for value in [VERSION, self.width, self.height, self.max_bounces] {
+			writer.write_all(&value.to_le_bytes())?;
+		}
+		write_scene_hash(&mut writer, self.scene_hash)?;
+		writer.write_all(&self.samples.to_le_bytes())?;
+		writer.write_all(&(self.seeds.len() as u32).to_le_bytes())?;
+		for seed in &self.seeds {
+			writer.write_all(&seed.to_le_bytes())?;
+		}
+		for samples in self.pixel_samples.iter() {
+			writer.write_all(&samples.to_le_bytes())?;
+		}
+		for value in self.pixels.iter() {
+			writer.write_all(&value.to_le_bytes())?;
+		}
+		writer.flush()
+	}
+
+	pub fn load(path: &Path) -> io::Result<Self> {
+		let mut reader = BufReader::new(File::open(path)?);
+
+		let mut magic = [0; 4];
+		reader.read_exact(&mut magic)?;
+		if &magic != MAGIC || read_u32(&mut reader)? != VERSION {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidData,
+				"not a raw image, or from an incompatible version",
+			));
+		}
+
+		let width = read_u32(&mut reader)?;
+		let height = read_u32(&mut reader)?;
+		let max_bounces = read_u32(&mut reader)?;
+		let scene_hash = read_scene_hash(&mut reader)?;
+		let samples = read_u32(&mut reader)?;
+		let n_seeds = read_u32(&mut reader)?;
+		let seeds = read_array(&mut reader, &[n_seeds], read_u64)?;
+		let pixel_samples = read_array(&mut reader, &[width, height], read_u32)?;
+		let pixels = read_array(&mut reader, &[width, height, 3], read_f32)?;
+
+		Ok(Self {
+			width,
+			height,
+			max_bounces,
+			scene_hash,
+			samples,
+			pixel_samples,
+			seeds,
+			pixels,
+		})
+	}
+}
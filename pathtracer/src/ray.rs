@@ -1,18 +1,27 @@
 use euclid::default::{Point3D, Vector3D};
 
-use crate::{hittable::Hittable, material, scene::Scene};
+use crate::{
+	hittable::Hittable,
+	material::{self, Material},
+	medium::{AnyMedium, Medium},
+	scene::Scene,
+};
 
 #[derive(Debug)]
 pub struct Ray {
 	pub origin: Point3D<f32>,
 	pub dir: Vector3D<f32>,
+
+	/// The moment within the camera shutter interval at which this ray is cast.
+	pub time: f32,
 }
 
 impl Ray {
-	pub fn new(origin: Point3D<f32>, dir: Vector3D<f32>) -> Self {
+	pub fn new(origin: Point3D<f32>, dir: Vector3D<f32>, time: f32) -> Self {
 		Self {
 			origin,
 			dir: dir.normalize(),
+			time,
 		}
 	}
 
@@ -21,18 +30,51 @@ impl Ray {
 		self.origin + self.dir * t
 	}
 
-	pub fn cast(&self, scene: &Scene, background: Vector3D<f32>, depth: u32) -> Vector3D<f32> {
+	/// Traces this ray through the `scene`, starting inside `medium`, and
+	/// inside an object with the `interior` material if there is one.
+	pub fn cast(
+		&self,
+		scene: &Scene,
+		background: Vector3D<f32>,
+		medium: Option<&AnyMedium>,
+		interior: Option<&Material>,
+		depth: u32,
+	) -> Vector3D<f32> {
 		if depth == 0 {
 			return Vector3D::zero();
 		}
 
-		let Some(hit) = scene.hit(self, 0.001..f32::MAX) else {
-			return background
-		};
+		let hit = scene.hit(self, 0.001..f32::MAX);
+
+		if let Some(medium) = medium {
+			let max_distance = hit.as_ref().map_or(f32::MAX, |h| h.distance);
+			if let Some((ray, colour)) = medium.scatter(self, max_distance) {
+				let distance = self.origin.distance_to(ray.origin);
+				let colour = colour.component_mul(material::absorb(interior, distance));
+				return ray
+					.cast(scene, background, Some(medium), interior, depth - 1)
+					.component_mul(colour);
+			}
+		}
+
+		let Some(hit) = hit else { return background };
 
 		let (ray, colour) = material::bounce(self, &hit);
+		let colour = colour.component_mul(material::absorb(interior, hit.distance));
 		if let Some(ray) = ray {
-			ray.cast(scene, background, depth - 1).component_mul(colour)
+			let entering = self.dir.dot(hit.normal) < 0.0;
+			let (medium, interior) = if entering == (ray.dir.dot(hit.normal) < 0.0) {
+				// The ray crossed the surface
+				if entering {
+					(hit.material.medium.as_ref(), Some(hit.material))
+				} else {
+					(scene.medium.as_ref(), None)
+				}
+			} else {
+				(medium, interior)
+			};
+			ray.cast(scene, background, medium, interior, depth - 1)
+				.component_mul(colour)
 		} else {
 			colour
 		}
@@ -1,23 +1,180 @@
 use core::ops::Range;
+use std::{
+	fs,
+	path::{Path, PathBuf},
+	sync::Arc,
+};
 
-use euclid::default::Vector3D;
-use serde::Deserialize;
+use euclid::default::{Point3D, Vector3D};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 use crate::{
-	camera::Camera,
-	hittable::{Hit, Hittable, HittableObject},
+	assets::{self, AssetPaths},
+	camera::{Camera, SerializedCamera},
+	gltf::{self, GltfScene},
+	hittable::{Hit, Hittable, HittableObject, Sphere, Triangle},
+	library::Library,
+	material::Material,
+	medium::AnyMedium,
 	ray::Ray,
+	transform::Transform,
+	Error,
 };
 
-#[derive(Debug, Clone, Deserialize)]
+/// A scene that can be rendered.
+///
+/// Scenes should be loaded with [`Scene::load`] or [`Scene::from_str_with_paths`].
+/// Deserializing a `Scene` with serde directly doesn't know where the scene
+/// file is, so files are resolved relative to the working directory, and
+/// includes and named materials and textures can't be used.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(try_from = "SerializedScene", into = "SerializedScene")]
 pub struct Scene {
 	pub objects: Vec<HittableObject>,
 	pub camera: Camera,
 	pub background_colour: Vector3D<f32>,
+
+	/// The participating medium that fills the whole scene, like fog.
+	pub medium: Option<AnyMedium>,
+
+	/// The axis that points up, which textures on spheres are wrapped around.
+	pub up_axis: UpAxis,
+}
+
+impl Scene {
+	/// Parses a scene from the contents of a TOML scene file. Files referenced
+	/// by the scene are resolved relative to the current working directory.
+	pub fn from_toml_str(scene_str: &str) -> Result<Self, Error> {
+		Self::from_toml_str_with_paths(scene_str, &AssetPaths::default())
+	}
+
+	/// Parses a scene from the contents of a TOML scene file, resolving
+	/// referenced files using `asset_paths`.
+	pub fn from_toml_str_with_paths(
+		scene_str: &str,
+		asset_paths: &AssetPaths,
+	) -> Result<Self, Error> {
+		Self::from_str_with_paths(scene_str, SceneFormat::Toml, asset_paths)
+	}
+
+	/// Parses a scene from the contents of a scene file in the given format,
+	/// resolving referenced files using `asset_paths`.
+	pub fn from_str_with_paths(
+		scene_str: &str,
+		format: SceneFormat,
+		asset_paths: &AssetPaths,
+	) -> Result<Self, Error> {
+		let mut library = Library::default();
+		let source = SceneSource::read(
+			None,
+			scene_str.to_string(),
+			format,
+			asset_paths.clone(),
+			&mut library,
+			&mut Vec::new(),
+		)?;
+		let scene = library.scope(|| source.load())?;
+		Scene::try_from(scene).map_err(|message| Error::Scene {
+			path: None,
+			line: None,
+			message,
+		})
+	}
+
+	/// Loads a scene from a TOML, JSON, RON or YAML scene file, depending on
+	/// its extension, or from a glTF file with a camera. Files referenced by
+	/// the scene are resolved relative to the scene file.
+	pub fn load(path: &Path) -> Result<Self, Error> {
+		Self::load_with_search_paths(path, Vec::new())
+	}
+
+	/// Loads a scene from a TOML, JSON, RON or YAML scene file, depending on
+	/// its extension, or from a glTF file with a camera. Files referenced by
+	/// the scene are resolved relative to the scene file, or else to one of
+	/// the `search_paths`.
+	pub fn load_with_search_paths(path: &Path, search_paths: Vec<PathBuf>) -> Result<Self, Error> {
+		if gltf::is_gltf(path) {
+			return Self::load_gltf(path);
+		}
+
+		let scene_str = fs::read_to_string(path).map_err(|source| Error::Io {
+			path: path.to_path_buf(),
+			source,
+		})?;
+		let asset_paths = AssetPaths::new(path.parent().map(Path::to_path_buf), search_paths);
+		let format = SceneFormat::from_path(path);
+		Self::from_str_with_paths(&scene_str, format, &asset_paths).map_err(|err| match err {
+			Error::Scene {
+				path: None,
+				line,
+				message,
+			} => Error::Scene {
+				path: Some(path.to_path_buf()),
+				line,
+				message,
+			},
+			err => err,
+		})
+	}
+
+	/// Writes the scene to `path`, in the format that matches its extension.
+	/// Paths to files used by the scene are written relative to `path`.
+	pub fn save(&self, path: &Path) -> Result<(), Error> {
+		let dir = match path.parent() {
+			Some(dir) if !dir.as_os_str().is_empty() => dir,
+			_ => Path::new("."),
+		};
+		let contents = assets::save_scope(dir, || {
+			self.to_string_with_format(SceneFormat::from_path(path))
+		})?;
+		fs::write(path, contents).map_err(|source| Error::Write {
+			path: path.to_path_buf(),
+			source,
+		})
+	}
+
+	/// Writes the scene as a TOML scene file. Named materials and textures
+	/// and included files are written out in full, and paths to files used by
+	/// the scene are absolute.
+	pub fn to_toml_string(&self) -> Result<String, Error> {
+		self.to_string_with_format(SceneFormat::Toml)
+	}
+
+	/// Writes the scene as JSON, with the same structure as a TOML scene file.
+	pub fn to_json_string(&self) -> Result<String, Error> {
+		self.to_string_with_format(SceneFormat::Json)
+	}
+
+	/// Writes the scene as a scene file in the given format.
+	pub fn to_string_with_format(&self, format: SceneFormat) -> Result<String, Error> {
+		let result = match format {
+			SceneFormat::Toml => toml::to_string(self).map_err(|err| err.to_string()),
+			SceneFormat::Json => serde_json::to_string_pretty(self).map_err(|err| err.to_string()),
+			SceneFormat::Ron => {
+				ron::ser::to_string_pretty(self, Default::default()).map_err(|err| err.to_string())
+			}
+			SceneFormat::Yaml => serde_yaml::to_string(self).map_err(|err| err.to_string()),
+		};
+		result.map_err(|message| Error::Serialize { message })
+	}
+
+	/// Loads a glTF file as a whole scene, with a black background.
+	fn load_gltf(path: &Path) -> Result<Self, Error> {
+		let mut scene = SerializedScene::from(gltf::load(path)?);
+		scene.background_colour = Some(Vector3D::zero());
+		// glTF is Y-up. This is only set for whole glTF scenes, so that an
+		// included glTF file doesn't change the axis of the scene including it.
+		scene.up_axis = Some(UpAxis::Y);
+		Scene::try_from(scene).map_err(|message| Error::Scene {
+			path: Some(path.to_path_buf()),
+			line: None,
+			message,
+		})
+	}
 }
 
 impl Hittable for Scene {
-	fn hit(&self, ray: &Ray, range: Range<f32>) -> Option<Hit> {
+	fn hit(&self, ray: &Ray, range: Range<f32>) -> Option<Hit<'_>> {
 		let mut closest_hit = None;
 
 		for object in &self.objects {
@@ -30,3 +187,699 @@ impl Hittable for Scene {
 		closest_hit
 	}
 }
+
+/// Builds a [`Scene`] in code, as an alternative to a scene file.
+///
+/// Objects get a light grey diffuse material, unless [`SceneBuilder::material`]
+/// is called right after adding them. The background is black by default.
+#[derive(Debug, Default)]
+pub struct SceneBuilder {
+	objects: Vec<HittableObject>,
+	camera: Option<Camera>,
+	background_colour: Vector3D<f32>,
+	medium: Option<AnyMedium>,
+	default_material: Arc<Material>,
+	up_axis: UpAxis,
+
+	/// The first mistake made while building, which is reported by
+	/// [`SceneBuilder::build`].
+	error: Option<String>,
+}
+
+impl SceneBuilder {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn camera(mut self, camera: Camera) -> Self {
+		self.camera = Some(camera);
+		self
+	}
+
+	pub fn background_colour(mut self, colour: Vector3D<f32>) -> Self {
+		self.background_colour = colour;
+		self
+	}
+
+	/// Sets the axis that points up, which spheres wrap their textures around.
+	/// The camera keeps its own up direction.
+	pub fn up_axis(mut self, up_axis: UpAxis) -> Self {
+		self.up_axis = up_axis;
+		self
+	}
+
+	/// Fills the whole scene with a participating medium, like fog.
+	pub fn medium(mut self, medium: impl Into<AnyMedium>) -> Self {
+		self.medium = Some(medium.into());
+		self
+	}
+
+	/// Adds a sphere with the default material.
+	pub fn sphere(self, centre: Point3D<f32>, radius: f32) -> Self {
+		let material = Arc::clone(&self.default_material);
+		let up_axis = self.up_axis;
+		self.object(Sphere {
+			centre,
+			radius,
+			material,
+			velocity: Vector3D::zero(),
+			up_axis,
+		})
+	}
+
+	/// Adds a triangle with the default material.
+	pub fn triangle(self, a: Point3D<f32>, b: Point3D<f32>, c: Point3D<f32>) -> Self {
+		let material = Arc::clone(&self.default_material);
+		self.object(Triangle {
+			a,
+			b,
+			c,
+			material,
+			velocity: Vector3D::zero(),
+			uv: None,
+			colours: None,
+		})
+	}
+
+	/// Adds any object, keeping its own material.
+	pub fn object(mut self, object: impl Into<HittableObject>) -> Self {
+		self.objects.push(object.into());
+		self
+	}
+
+	/// Sets the material of the object that was added last.
+	pub fn material(mut self, material: impl Into<Arc<Material>>) -> Self {
+		match self.objects.last_mut() {
+			Some(object) => object.set_material(material.into()),
+			None => {
+				let message = "a material was given before adding any objects";
+				self.error.get_or_insert_with(|| message.to_string());
+			}
+		}
+		self
+	}
+
+	/// Checks that the scene has a camera and that its objects and materials
+	/// make sense, and builds it.
+	pub fn build(self) -> Result<Scene, Error> {
+		self.validate()
+			.map_err(|message| Error::InvalidScene { message })?;
+		let camera = self.camera.ok_or_else(|| Error::InvalidScene {
+			message: "the scene has no camera".to_string(),
+		})?;
+		let mut objects = self.objects;
+		for object in &mut objects {
+			if let HittableObject::Sphere(sphere) = object {
+				sphere.up_axis = self.up_axis;
+			}
+		}
+		Ok(Scene {
+			objects,
+			camera,
+			background_colour: self.background_colour,
+			medium: self.medium,
+			up_axis: self.up_axis,
+		})
+	}
+
+	fn validate(&self) -> Result<(), String> {
+		if let Some(error) = &self.error {
+			return Err(error.clone());
+		}
+		let finite = |v: Vector3D<f32>| v.x.is_finite() && v.y.is_finite() && v.z.is_finite();
+		if let Some(camera) = &self.camera {
+			if !finite(camera.pos().to_vector()) || !finite(camera.dir()) {
+				return Err("the camera position and direction must be finite".to_string());
+			}
+		}
+
+		for (i, object) in self.objects.iter().enumerate() {
+			let (points, materials): (Vec<_>, Vec<_>) = match object {
+				HittableObject::Sphere(s) => {
+					if s.radius <= 0.0 || !s.radius.is_finite() {
+						return Err(format!("sphere {i} has a radius of {}", s.radius));
+					}
+					(vec![s.centre], vec![&s.material])
+				}
+				HittableObject::Triangle(t) => (vec![t.a, t.b, t.c], vec![&t.material]),
+				HittableObject::Mesh(m) => (
+					m.triangles().iter().flat_map(|t| [t.a, t.b, t.c]).collect(),
+					m.triangles().iter().map(|t| &t.material).collect(),
+				),
+			};
+			if !points.iter().all(|p| finite(p.to_vector())) {
+				return Err(format!("object {i} has a position that is not finite"));
+			}
+			for material in materials {
+				validate_material(material).map_err(|message| format!("object {i}: {message}"))?;
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Checks that the fractions of a material are between `0.0` and `1.0`.
+fn validate_material(material: &Material) -> Result<(), String> {
+	let fractions = [
+		("emission", material.emission),
+		("metallic", material.metallic),
+		("specular", material.specular),
+		("transparency", material.transparency),
+		("roughness", material.roughness),
+	];
+	for (name, value) in fractions {
+		if !(0.0..=1.0).contains(&value) {
+			return Err(format!("`{name}` is {value}, but must be between 0 and 1"));
+		}
+	}
+	if material.ior <= 0.0 || material.ior.is_nan() {
+		return Err(format!("`ior` is {}, but must be positive", material.ior));
+	}
+	Ok(())
+}
+
+/// The formats that scene files can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SceneFormat {
+	Toml,
+	Json,
+	Ron,
+	Yaml,
+}
+
+impl SceneFormat {
+	/// Returns the format of a scene file based on its extension. Files with
+	/// other extensions are read as TOML.
+	pub fn from_path(path: &Path) -> Self {
+		let extension = path
+			.extension()
+			.map(|extension| extension.to_ascii_lowercase());
+		match extension.as_ref().and_then(|extension| extension.to_str()) {
+			Some("json") => SceneFormat::Json,
+			Some("ron") => SceneFormat::Ron,
+			Some("yaml" | "yml") => SceneFormat::Yaml,
+			_ => SceneFormat::Toml,
+		}
+	}
+
+	/// Deserializes the contents of a scene file, with the line of the error
+	/// if there is one.
+	fn parse<T: DeserializeOwned>(self, contents: &str, path: Option<&Path>) -> Result<T, Error> {
+		let line_at = |offset: usize| contents[..offset].chars().filter(|&c| c == '\n').count() + 1;
+		let (message, line) = match self {
+			SceneFormat::Toml => match toml::from_str(contents) {
+				Ok(value) => return Ok(value),
+				Err(err) => (
+					err.message().to_string(),
+					err.span().map(|span| line_at(span.start)),
+				),
+			},
+			SceneFormat::Json => match serde_json::from_str(contents) {
+				Ok(value) => return Ok(value),
+				// The message already includes the line and column
+				Err(err) => (err.to_string(), None),
+			},
+			// RON can only deserialize flattened fields from maps, not from
+			// structs, so it is read into a JSON value first. This also allows
+			// leaving out `Some` around optional values.
+			SceneFormat::Ron => match ron::from_str::<serde_json::Value>(contents) {
+				Ok(value) => match serde_json::from_value(value) {
+					Ok(value) => return Ok(value),
+					Err(err) => (err.to_string(), None),
+				},
+				Err(err) => (err.code.to_string(), Some(err.span.start.line)),
+			},
+			SceneFormat::Yaml => match serde_yaml::from_str(contents) {
+				Ok(value) => return Ok(value),
+				Err(err) => (err.to_string(), None),
+			},
+		};
+		Err(Error::Scene {
+			path: path.map(Path::to_path_buf),
+			line,
+			message,
+		})
+	}
+}
+
+/// The axis that points up in a scene.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+pub enum UpAxis {
+	Y,
+	#[default]
+	Z,
+}
+
+impl UpAxis {
+	pub fn vector(self) -> Vector3D<f32> {
+		match self {
+			UpAxis::Y => Vector3D::new(0.0, 1.0, 0.0),
+			UpAxis::Z => Vector3D::new(0.0, 0.0, 1.0),
+		}
+	}
+}
+
+/// The contents of a scene file and of all the files it includes. These are
+/// read before anything else is loaded, so that all named materials and
+/// textures are known.
+struct SceneSource {
+	path: Option<PathBuf>,
+	contents: String,
+	format: SceneFormat,
+	asset_paths: AssetPaths,
+	includes: Vec<(IncludedSource, Transform)>,
+}
+
+enum IncludedSource {
+	Scene(SceneSource),
+	Gltf(PathBuf),
+}
+
+/// The parts of a scene file that are needed to read the files it includes.
+#[derive(Deserialize)]
+struct SceneHeader {
+	#[serde(default)]
+	include: Vec<Include>,
+	#[serde(flatten)]
+	library: Library,
+}
+
+/// Another scene file whose objects, named materials and textures are added
+/// to this scene. Its camera, background colour and medium are only used if
+/// the including file does not have one. glTF files can be included as well,
+/// for their objects and camera.
+#[derive(Deserialize)]
+struct Include {
+	path: PathBuf,
+	#[serde(flatten)]
+	transform: Transform,
+}
+
+impl SceneSource {
+	/// Reads the file and everything it includes, adding their named items to
+	/// `library`. Items in the including file take precedence.
+	fn read(
+		path: Option<PathBuf>,
+		contents: String,
+		format: SceneFormat,
+		asset_paths: AssetPaths,
+		library: &mut Library,
+		stack: &mut Vec<PathBuf>,
+	) -> Result<Self, Error> {
+		let header: SceneHeader = asset_paths.scope(|| format.parse(&contents, path.as_deref()))?;
+		library.extend(header.library);
+
+		let mut includes = Vec::new();
+		for include in header.include {
+			let include_path = asset_paths.resolve(&include.path);
+			if gltf::is_gltf(&include_path) {
+				includes.push((IncludedSource::Gltf(include_path), include.transform));
+				continue;
+			}
+
+			let canonical_path = include_path.canonicalize().unwrap_or(include_path.clone());
+			if stack.contains(&canonical_path) {
+				return Err(Error::Scene {
+					path: path.clone(),
+					line: None,
+					message: format!("{} includes itself", include_path.display()),
+				});
+			}
+
+			let include_contents =
+				fs::read_to_string(&include_path).map_err(|source| Error::Io {
+					path: include_path.clone(),
+					source,
+				})?;
+			let include_asset_paths = AssetPaths::new(
+				include_path.parent().map(Path::to_path_buf),
+				asset_paths.search_paths.clone(),
+			);
+			stack.push(canonical_path);
+			let source = SceneSource::read(
+				Some(include_path.clone()),
+				include_contents,
+				SceneFormat::from_path(&include_path),
+				include_asset_paths,
+				library,
+				stack,
+			)?;
+			stack.pop();
+			includes.push((IncludedSource::Scene(source), include.transform));
+		}
+
+		Ok(Self {
+			path,
+			contents,
+			format,
+			asset_paths,
+			includes,
+		})
+	}
+
+	/// Deserializes the scene, merging in the included scenes.
+	fn load(self) -> Result<SerializedScene, Error> {
+		let mut scene: SerializedScene = self
+			.asset_paths
+			.scope(|| self.format.parse(&self.contents, self.path.as_deref()))?;
+		for (source, transform) in self.includes {
+			let mut included = match source {
+				IncludedSource::Scene(source) => source.load()?,
+				IncludedSource::Gltf(path) => gltf::load(&path)?.into(),
+			};
+			included.transform(&transform);
+			scene.merge(included);
+		}
+		Ok(scene)
+	}
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct SerializedScene {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	up_axis: Option<UpAxis>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	background_colour: Option<Vector3D<f32>>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	camera: Option<SerializedCamera>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	medium: Option<AnyMedium>,
+	#[serde(default)]
+	objects: Vec<HittableObject>,
+}
+
+impl SerializedScene {
+	fn transform(&mut self, transform: &Transform) {
+		for object in &mut self.objects {
+			object.transform(transform);
+		}
+		if let Some(camera) = &mut self.camera {
+			camera.transform(transform);
+		}
+	}
+
+	/// Adds the objects of `other`, and uses its settings where this scene
+	/// has none.
+	fn merge(&mut self, other: SerializedScene) {
+		self.objects.extend(other.objects);
+		self.camera = self.camera.take().or(other.camera);
+		self.background_colour = self.background_colour.or(other.background_colour);
+		self.medium = self.medium.take().or(other.medium);
+		self.up_axis = self.up_axis.or(other.up_axis);
+	}
+}
+
+impl From<GltfScene> for SerializedScene {
+	fn from(value: GltfScene) -> Self {
+		Self {
+			objects: value.objects,
+			camera: value.camera,
+			background_colour: None,
+			medium: None,
+			up_axis: None,
+		}
+	}
+}
+
+impl From<Scene> for SerializedScene {
+	fn from(value: Scene) -> Self {
+		Self {
+			up_axis: Some(value.up_axis),
+			background_colour: Some(value.background_colour),
+			camera: Some(value.camera.into()),
+			medium: value.medium,
+			objects: value.objects,
+		}
+	}
+}
+
+impl TryFrom<SerializedScene> for Scene {
+	type Error = String;
+
+	fn try_from(value: SerializedScene) -> Result<Self, Self::Error> {
+		let camera = value.camera.ok_or("missing field `camera`")?;
+		let up_axis = value.up_axis.unwrap_or_default();
+		let mut objects = value.objects;
+		for object in &mut objects {
+			if let HittableObject::Sphere(sphere) = object {
+				sphere.up_axis = up_axis;
+			}
+		}
+		Ok(Scene {
+			objects,
+			camera: camera.into_camera(up_axis.vector())?,
+			background_colour: value
+				.background_colour
+				.ok_or("missing field `background_colour`")?,
+			medium: value.medium,
+			up_axis,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fs::File;
+
+	use super::*;
+	use crate::{camera::Projection, hittable::Mesh, texture::SolidColour};
+
+	const FORMATS: [SceneFormat; 4] = [
+		SceneFormat::Toml,
+		SceneFormat::Json,
+		SceneFormat::Ron,
+		SceneFormat::Yaml,
+	];
+
+	fn camera() -> Camera {
+		Camera::from_look_at(
+			Point3D::new(0.0, -2.0, 0.0),
+			Point3D::origin(),
+			1.0,
+			40.0,
+			0.0,
+			Projection::Perspective,
+		)
+	}
+
+	/// Returns the message of the error that building `builder` gives.
+	fn build_error(builder: SceneBuilder) -> String {
+		match builder.build() {
+			Err(Error::InvalidScene { message }) => message,
+			other => panic!("expected an invalid scene, got {other:?}"),
+		}
+	}
+
+	#[test]
+	fn build_requires_camera() {
+		let builder = SceneBuilder::new().sphere(Point3D::origin(), 1.0);
+		assert_eq!(build_error(builder), "the scene has no camera");
+	}
+
+	#[test]
+	fn material_before_objects() {
+		let builder = SceneBuilder::new()
+			.camera(camera())
+			.material(Material::default())
+			.sphere(Point3D::origin(), 1.0);
+		assert_eq!(
+			build_error(builder),
+			"a material was given before adding any objects"
+		);
+	}
+
+	#[test]
+	fn build_rejects_bad_radius() {
+		for radius in [0.0, -1.0, f32::NAN] {
+			let builder = SceneBuilder::new()
+				.camera(camera())
+				.sphere(Point3D::origin(), radius);
+			assert!(build_error(builder).starts_with("sphere 0 has a radius of"));
+		}
+	}
+
+	#[test]
+	fn build_rejects_fractions_out_of_range() {
+		let material = Material {
+			metallic: 1.5,
+			..Material::diffuse(SolidColour::new(0.5, 0.5, 0.5).into())
+		};
+		let builder = SceneBuilder::new()
+			.camera(camera())
+			.sphere(Point3D::origin(), 1.0)
+			.material(material);
+		assert_eq!(
+			build_error(builder),
+			"object 0: `metallic` is 1.5, but must be between 0 and 1"
+		);
+	}
+
+	#[test]
+	fn up_axis_applies_to_all_spheres() {
+		let sphere = Sphere {
+			centre: Point3D::origin(),
+			radius: 1.0,
+			material: Arc::new(Material::default()),
+			velocity: Vector3D::zero(),
+			up_axis: UpAxis::Z,
+		};
+		let scene = SceneBuilder::new()
+			.camera(camera())
+			.object(sphere)
+			.up_axis(UpAxis::Y)
+			.sphere(Point3D::origin(), 1.0)
+			.build()
+			.unwrap();
+		assert!(matches!(scene.up_axis, UpAxis::Y));
+		for object in &scene.objects {
+			assert!(matches!(object, HittableObject::Sphere(s) if matches!(s.up_axis, UpAxis::Y)));
+		}
+	}
+
+	/// Returns an empty directory for the files of a test.
+	fn temp_dir(name: &str) -> PathBuf {
+		let dir = std::env::temp_dir().join(format!("pathtracer-{name}-{}", std::process::id()));
+		let _ = fs::remove_dir_all(&dir);
+		fs::create_dir_all(&dir).unwrap();
+		dir
+	}
+
+	/// Writes `scene` in `format`, loads it again and checks that nothing was
+	/// lost, returning the loaded scene.
+	fn round_trip(scene: &Scene, format: SceneFormat) -> Scene {
+		let written = scene.to_string_with_format(format).unwrap();
+		let loaded = Scene::from_str_with_paths(&written, format, &AssetPaths::default()).unwrap();
+		assert_eq!(loaded.to_string_with_format(format).unwrap(), written);
+		assert_eq!(
+			loaded.to_json_string().unwrap(),
+			scene.to_json_string().unwrap()
+		);
+		loaded
+	}
+
+	#[test]
+	fn scene_files_round_trip() {
+		let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../scenes");
+		for entry in fs::read_dir(dir).unwrap() {
+			let path = entry.unwrap().path();
+			if SceneFormat::from_path(&path) != SceneFormat::Toml {
+				continue;
+			}
+			let scene = Scene::load(&path).unwrap();
+			for format in FORMATS {
+				round_trip(&scene, format);
+			}
+		}
+	}
+
+	#[test]
+	fn camera_round_trips() {
+		let scene = Scene::from_toml_str(
+			"background_colour = [0.0, 0.0, 0.0]\n\
+			[camera]\npos = [1.0, 2.0, 3.0]\nlook_at = [0.0, 1.0, 0.0]\nfov = 40.0\n\
+			aperture = 0.1\nexposure = 2.0\n",
+		)
+		.unwrap();
+		let physical = Scene::from_toml_str(
+			"background_colour = [0.0, 0.0, 0.0]\n\
+			[camera]\npos = [1.0, 2.0, 3.0]\nlook_at = [0.0, 1.0, 0.0]\n\
+			physical = { focal_length = 50.0, f_number = 2.8, shutter_speed = 0.01, iso = 100.0 }\n",
+		)
+		.unwrap();
+		for format in FORMATS {
+			let loaded = round_trip(&scene, format);
+			assert_eq!(loaded.camera.look_at(), Point3D::new(0.0, 1.0, 0.0));
+			assert_eq!(loaded.camera.exposure(), 2.0);
+
+			let loaded = round_trip(&physical, format);
+			assert_eq!(loaded.camera.look_at(), Point3D::new(0.0, 1.0, 0.0));
+			assert!(loaded.camera.physical().is_some());
+			assert_eq!(loaded.camera.exposure(), physical.camera.exposure());
+		}
+	}
+
+	#[test]
+	fn save_writes_relative_texture_paths() {
+		let dir = temp_dir("textures");
+		fs::create_dir(dir.join("textures")).unwrap();
+		let mut encoder =
+			png::Encoder::new(File::create(dir.join("textures/red.png")).unwrap(), 1, 1);
+		encoder.set_color(png::ColorType::Rgb);
+		let mut writer = encoder.write_header().unwrap();
+		writer.write_image_data(&[255, 0, 0]).unwrap();
+		writer.finish().unwrap();
+		fs::write(
+			dir.join("scene.toml"),
+			"background_colour = [0.0, 0.0, 0.0]\n\
+			[camera]\npos = [0.0, -2.0, 0.0]\nlook_at = [0.0, 0.0, 0.0]\nfov = 40.0\n\
+			[[objects]]\ntype = \"Sphere\"\ncentre = [0.0, 0.0, 0.0]\nradius = 1.0\n\
+			material = { texture = { type = \"ImageTexture\", image = \"textures/red.png\" } }\n",
+		)
+		.unwrap();
+
+		let scene = Scene::load(&dir.join("scene.toml")).unwrap();
+		fs::create_dir(dir.join("saved")).unwrap();
+		for (name, format) in ["scene.toml", "scene.json", "scene.ron", "scene.yaml"]
+			.into_iter()
+			.zip(FORMATS)
+		{
+			let path = dir.join("saved").join(name);
+			scene.save(&path).unwrap();
+			assert!(fs::read_to_string(&path)
+				.unwrap()
+				.contains("../textures/red.png"));
+			let loaded = Scene::load(&path).unwrap();
+			assert_eq!(
+				loaded.to_string_with_format(format).unwrap(),
+				scene.to_string_with_format(format).unwrap()
+			);
+		}
+		fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[test]
+	fn meshes_keep_their_source() {
+		let dir = temp_dir("meshes");
+		fs::write(
+			dir.join("quad.ply"),
+			"ply\nformat ascii 1.0\nelement vertex 4\nproperty float x\nproperty float y\n\
+			property float z\nelement face 1\nproperty list uchar int vertex_indices\n\
+			end_header\n0 0 0\n1 0 0\n1 1 0\n0 1 0\n4 0 1 2 3\n",
+		)
+		.unwrap();
+		fs::write(
+			dir.join("scene.toml"),
+			"background_colour = [0.0, 0.0, 0.0]\n\
+			[camera]\npos = [0.0, -2.0, 0.0]\nlook_at = [0.0, 0.0, 0.0]\nfov = 40.0\n\
+			[[objects]]\ntype = \"Mesh\"\npath = \"quad.ply\"\nmaterial = { colour = [1.0, 0.0, 0.0] }\n\
+			translate = [0.0, 0.0, 1.0]\nscale = 2.0\n",
+		)
+		.unwrap();
+
+		let scene = Scene::load(&dir.join("scene.toml")).unwrap();
+		let saved = dir.join("saved.toml");
+		scene.save(&saved).unwrap();
+		let contents = fs::read_to_string(&saved).unwrap();
+		assert!(contents.contains("path = \"quad.ply\""));
+		assert!(!contents.contains("triangles"));
+
+		let loaded = Scene::load(&saved).unwrap();
+		let [HittableObject::Mesh(original), HittableObject::Mesh(reloaded)] =
+			[&scene.objects[0], &loaded.objects[0]]
+		else {
+			panic!("expected meshes");
+		};
+		let corners = |mesh: &Mesh| -> Vec<_> {
+			mesh.triangles()
+				.iter()
+				.flat_map(|t| [t.a, t.b, t.c])
+				.collect()
+		};
+		assert_eq!(corners(reloaded), corners(original));
+		assert_eq!(corners(original)[1], Point3D::new(2.0, 0.0, 1.0));
+		for format in FORMATS {
+			round_trip(&scene, format);
+		}
+		fs::remove_dir_all(dir).unwrap();
+	}
+}
@@ -1,21 +1,34 @@
-use std::{fs::File, path::PathBuf};
+use std::{fs::File, path::PathBuf, sync::Arc};
 
 use euclid::default::{Point3D, Vector2D, Vector3D};
-use serde::Deserialize;
+use serde::{ser, Deserialize, Serialize, Serializer};
 
-use crate::util;
+use crate::{assets, hittable::Hit, library, util, Error};
 
 pub trait Texture {
 	fn colour(&self, uv: Vector2D<f32>, point: Point3D<f32>) -> Vector3D<f32>;
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(tag = "type")]
 pub enum AnyTexture {
 	SolidColour(SolidColour),
 	CheckerTexture(CheckerTexture),
 	ImageTexture(ImageTexture),
 	UVTexture(UVTexture),
+	VertexColour(VertexColour),
+}
+
+impl AnyTexture {
+	/// Returns the colour of the texture at `hit`. Unlike [`Texture::colour`],
+	/// this includes the vertex colours of meshes.
+	pub fn colour_at(&self, hit: &Hit) -> Vector3D<f32> {
+		match self {
+			AnyTexture::CheckerTexture(t) => t.pick(hit.point).colour_at(hit),
+			AnyTexture::VertexColour(t) => hit.vertex_colour.unwrap_or(t.colour),
+			t => t.colour(hit.uv, hit.point),
+		}
+	}
 }
 
 impl Texture for AnyTexture {
@@ -25,13 +38,14 @@ impl Texture for AnyTexture {
 			AnyTexture::CheckerTexture(t) => t.colour(uv, point),
 			AnyTexture::ImageTexture(t) => t.colour(uv, point),
 			AnyTexture::UVTexture(t) => t.colour(uv, point),
+			AnyTexture::VertexColour(t) => t.colour(uv, point),
 		}
 	}
 }
 
-#[derive(Debug, Clone, Copy, Deserialize)]
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
 pub struct SolidColour {
-	colour: Vector3D<f32>,
+	pub(crate) colour: Vector3D<f32>,
 }
 
 impl SolidColour {
@@ -54,23 +68,39 @@ impl Texture for SolidColour {
 	}
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct CheckerTexture {
-	even: Box<AnyTexture>,
-	odd: Box<AnyTexture>,
+	#[serde(deserialize_with = "library::deserialize")]
+	even: Arc<AnyTexture>,
+	#[serde(deserialize_with = "library::deserialize")]
+	odd: Arc<AnyTexture>,
 	scale: f32,
 }
 
 impl CheckerTexture {
 	pub fn new(even: AnyTexture, odd: AnyTexture, scale: f32) -> Self {
 		Self {
-			even: Box::new(even),
-			odd: Box::new(odd),
+			even: Arc::new(even),
+			odd: Arc::new(odd),
 			scale,
 		}
 	}
 }
 
+impl CheckerTexture {
+	/// Returns the texture of the square that `point` is in.
+	fn pick(&self, point: Point3D<f32>) -> &AnyTexture {
+		let sines = (point.x * self.scale).sin()
+			* (point.y * self.scale).sin()
+			* (point.z * self.scale).sin();
+		if sines < 0.0 {
+			&self.odd
+		} else {
+			&self.even
+		}
+	}
+}
+
 impl From<CheckerTexture> for AnyTexture {
 	fn from(value: CheckerTexture) -> Self {
 		AnyTexture::CheckerTexture(value)
@@ -79,18 +109,11 @@ impl From<CheckerTexture> for AnyTexture {
 
 impl Texture for CheckerTexture {
 	fn colour(&self, uv: Vector2D<f32>, point: Point3D<f32>) -> Vector3D<f32> {
-		let sines = (point.x * self.scale).sin()
-			* (point.y * self.scale).sin()
-			* (point.z * self.scale).sin();
-		if sines < 0.0 {
-			self.odd.colour(uv, point)
-		} else {
-			self.even.colour(uv, point)
-		}
+		self.pick(point).colour(uv, point)
 	}
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct UVTexture;
 
 impl From<UVTexture> for AnyTexture {
@@ -105,12 +128,46 @@ impl Texture for UVTexture {
 	}
 }
 
+/// The colours at the vertices of a mesh, blended across its faces. Objects
+/// without vertex colours get `colour` instead.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct VertexColour {
+	#[serde(default = "VertexColour::default_colour")]
+	colour: Vector3D<f32>,
+}
+
+impl VertexColour {
+	pub fn new(colour: Vector3D<f32>) -> Self {
+		Self { colour }
+	}
+
+	fn default_colour() -> Vector3D<f32> {
+		Vector3D::new(0.8, 0.8, 0.8)
+	}
+}
+
+impl From<VertexColour> for AnyTexture {
+	fn from(value: VertexColour) -> Self {
+		AnyTexture::VertexColour(value)
+	}
+}
+
+impl Texture for VertexColour {
+	fn colour(&self, _uv: Vector2D<f32>, _point: Point3D<f32>) -> Vector3D<f32> {
+		self.colour
+	}
+}
+
 #[derive(Debug, Clone, Deserialize)]
-#[serde(from = "SerializedImageTexture")]
+#[serde(try_from = "SerializedImageTexture")]
 pub struct ImageTexture {
 	image: Box<[u8]>,
 	width: u32,
 	height: u32,
+
+	/// The path the image was loaded from. Images without one, like textures
+	/// embedded in glTF files, cannot be serialized.
+	path: Option<PathBuf>,
 }
 
 impl ImageTexture {
@@ -120,22 +177,58 @@ impl ImageTexture {
 			image,
 			width,
 			height,
+			path: None,
 		}
 	}
 
-	pub fn from_path(path: PathBuf) -> Self {
-		let decoder = png::Decoder::new(File::open(path).unwrap());
-		let mut reader = decoder.read_info().unwrap();
-		let mut buf = vec![0; reader.output_buffer_size()];
-		let info = reader.next_frame(&mut buf).unwrap();
+	/// Loads a PNG image. Images with other colour types or bit depths are
+	/// converted to 8-bit RGB. The texture is serialized as a reference to
+	/// `path`.
+	pub fn from_path(path: PathBuf) -> Result<Self, Error> {
+		let file = File::open(&path).map_err(|source| Error::Io {
+			path: path.clone(),
+			source,
+		})?;
+		let mut decoder = png::Decoder::new(file);
+		decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+		let decode = || {
+			let mut reader = decoder.read_info()?;
+			let mut buf = vec![0; reader.output_buffer_size()];
+			let info = reader.next_frame(&mut buf)?;
+			buf.truncate(info.buffer_size());
+			Ok((buf, info))
+		};
+		let (buf, info) = decode().map_err(|source| Error::Image {
+			path: path.clone(),
+			source,
+		})?;
 
-		assert_eq!(info.bit_depth, png::BitDepth::Eight);
+		let image = match info.color_type {
+			png::ColorType::Rgb => buf,
+			png::ColorType::Rgba => buf
+				.chunks_exact(4)
+				.flat_map(|pixel| [pixel[0], pixel[1], pixel[2]])
+				.collect(),
+			png::ColorType::Grayscale => buf.iter().flat_map(|&v| [v, v, v]).collect(),
+			png::ColorType::GrayscaleAlpha => buf
+				.chunks_exact(2)
+				.flat_map(|pixel| [pixel[0]; 3])
+				.collect(),
+			// Expanded into RGB(A) by the decoder
+			png::ColorType::Indexed => unreachable!(),
+		};
 
-		Self {
-			image: buf.into_boxed_slice(),
-			width: info.width,
-			height: info.height,
-		}
+		let mut texture = Self::new(image.into_boxed_slice(), info.width, info.height);
+		texture.path = Some(path);
+		Ok(texture)
+	}
+
+	pub fn width(&self) -> u32 {
+		self.width
+	}
+
+	pub fn height(&self) -> u32 {
+		self.height
 	}
 }
 
@@ -147,21 +240,35 @@ impl From<ImageTexture> for AnyTexture {
 
 impl Texture for ImageTexture {
 	fn colour(&self, uv: Vector2D<f32>, _point: Point3D<f32>) -> Vector3D<f32> {
-		let x = (uv.x * (self.width - 1) as f32) as usize;
-		let y = ((1.0 - uv.y) * (self.height - 1) as f32) as usize;
+		// The image repeats outside of the range 0 to 1
+		let (u, v) = (uv.x.rem_euclid(1.0), uv.y.rem_euclid(1.0));
+		let x = (u * (self.width - 1) as f32) as usize;
+		let y = ((1.0 - v) * (self.height - 1) as f32) as usize;
 		let idx = (x + y * self.width as usize) * 3;
 
 		util::colour_u8_to_f32([self.image[idx], self.image[idx + 1], self.image[idx + 2]])
 	}
 }
 
-#[derive(Debug, Clone, Deserialize)]
+impl Serialize for ImageTexture {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		let path = self.path.as_ref().ok_or_else(|| {
+			ser::Error::custom("cannot save an embedded image texture, like one from a glTF file")
+		})?;
+		let image = assets::saved_path(path);
+		SerializedImageTexture { image }.serialize(serializer)
+	}
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 struct SerializedImageTexture {
 	image: PathBuf,
 }
 
-impl From<SerializedImageTexture> for ImageTexture {
-	fn from(value: SerializedImageTexture) -> Self {
-		ImageTexture::from_path(value.image)
+impl TryFrom<SerializedImageTexture> for ImageTexture {
+	type Error = Error;
+
+	fn try_from(value: SerializedImageTexture) -> Result<Self, Self::Error> {
+		ImageTexture::from_path(assets::resolve(&value.image))
 	}
 }
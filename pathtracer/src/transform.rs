@@ -0,0 +1,137 @@
+use euclid::{
+	default::{Point3D, Rotation3D, Vector3D},
+	Angle,
+};
+use serde::{de, Deserialize, Deserializer, Serialize};
+
+/// Scales, then rotates, then translates points.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Transform {
+	pub translate: Vector3D<f32>,
+
+	/// The rotation around the x, y and z axes, in degrees.
+	pub rotate: Vector3D<f32>,
+
+	/// A uniform scale factor, so that spheres stay spheres.
+	#[serde(deserialize_with = "deserialize_scale")]
+	pub scale: f32,
+}
+
+fn deserialize_scale<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f32, D::Error> {
+	let scale = f32::deserialize(deserializer)?;
+	if scale > 0.0 && scale.is_finite() {
+		Ok(scale)
+	} else {
+		Err(de::Error::custom(format!(
+			"`scale` is {scale}, but must be positive"
+		)))
+	}
+}
+
+impl Default for Transform {
+	fn default() -> Self {
+		Self {
+			translate: Vector3D::zero(),
+			rotate: Vector3D::zero(),
+			scale: 1.0,
+		}
+	}
+}
+
+impl Transform {
+	fn rotation(&self) -> Rotation3D<f32> {
+		Rotation3D::euler(
+			Angle::degrees(self.rotate.x),
+			Angle::degrees(self.rotate.y),
+			Angle::degrees(self.rotate.z),
+		)
+	}
+
+	pub fn point(&self, point: Point3D<f32>) -> Point3D<f32> {
+		self.rotation().transform_point3d(point * self.scale) + self.translate
+	}
+
+	/// Transforms a direction or offset, which is not affected by translation.
+	pub fn vector(&self, vector: Vector3D<f32>) -> Vector3D<f32> {
+		self.rotation().transform_vector3d(vector * self.scale)
+	}
+
+	pub fn is_identity(&self) -> bool {
+		*self == Self::default()
+	}
+
+	/// Returns the transform that applies this one and then `other`.
+	pub fn then(&self, other: &Transform) -> Transform {
+		if self.is_identity() {
+			return *other;
+		} else if other.is_identity() {
+			return *self;
+		}
+
+		// Extract the Euler angles from the columns of the rotation matrix,
+		// which is Rz * Ry * Rx
+		let rotation = self.rotation().then(&other.rotation());
+		let [x, y, z] = [
+			Vector3D::new(1.0, 0.0, 0.0),
+			Vector3D::new(0.0, 1.0, 0.0),
+			Vector3D::new(0.0, 0.0, 1.0),
+		]
+		.map(|axis| rotation.transform_vector3d(axis));
+		let pitch = (-x.z).atan2(x.x.hypot(x.y));
+		let (roll, yaw) = if x.z.abs() < 0.9999 {
+			(y.z.atan2(z.z), x.y.atan2(x.x))
+		} else {
+			// Looking straight along the z axis, where only yaw and roll
+			// combined are known
+			(0.0, (-y.x).atan2(y.y))
+		};
+		Transform {
+			translate: other.vector(self.translate) + other.translate,
+			rotate: Vector3D::new(roll, pitch, yaw).map(f32::to_degrees),
+			scale: self.scale * other.scale,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn then_matches_applying_both() {
+		let transform = |translate: [f32; 3], rotate: [f32; 3], scale| Transform {
+			translate: translate.into(),
+			rotate: rotate.into(),
+			scale,
+		};
+		let pairs = [
+			(
+				transform([1.0, -2.0, 0.5], [30.0, -50.0, 120.0], 2.0),
+				transform([-3.0, 0.0, 4.0], [-70.0, 20.0, 10.0], 0.5),
+			),
+			// Pointing the x axis straight down
+			(
+				transform([0.0, 0.0, 0.0], [0.0, 90.0, 0.0], 1.0),
+				transform([0.0, 1.0, 0.0], [0.0, 0.0, 20.0], 1.0),
+			),
+		];
+		for (first, second) in pairs {
+			let combined = first.then(&second);
+			for point in [Point3D::new(1.0, 2.0, 3.0), Point3D::new(-4.0, 0.5, 0.0)] {
+				let expected = second.point(first.point(point));
+				assert!((combined.point(point) - expected).length() < 1e-4);
+			}
+		}
+	}
+
+	#[test]
+	fn then_keeps_angles_with_identity() {
+		let transform = Transform {
+			rotate: Vector3D::new(0.0, 0.0, 30.0),
+			..Transform::default()
+		};
+		assert_eq!(transform.then(&Transform::default()), transform);
+		assert_eq!(Transform::default().then(&transform), transform);
+	}
+}
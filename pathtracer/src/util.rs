@@ -1,5 +1,37 @@
+use std::{
+	cell::RefCell,
+	fs::File,
+	io::{self, BufReader, Read, Seek, Write},
+};
+
 use euclid::default::Vector3D;
-use rand::random;
+use rand::{
+	distributions::{Distribution, Standard},
+	rngs::StdRng,
+	Rng, SeedableRng,
+};
+
+use crate::Region;
+
+thread_local! {
+	static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
+/// Reseeds the random number generator of the current thread, such that every
+/// combination of `seed` and `stream` gives an independent sequence.
+pub fn seed_rng(seed: u64, stream: u64) {
+	let mut bytes = [0; 32];
+	bytes[..8].copy_from_slice(&seed.to_le_bytes());
+	bytes[8..16].copy_from_slice(&stream.to_le_bytes());
+	RNG.with(|rng| *rng.borrow_mut() = StdRng::from_seed(bytes));
+}
+
+pub fn random<T>() -> T
+where
+	Standard: Distribution<T>,
+{
+	RNG.with(|rng| rng.borrow_mut().gen())
+}
 
 pub fn coords_to_idx(x: u32, y: u32, width: u32) -> usize {
 	4 * (x + y * width) as usize
@@ -60,3 +92,88 @@ pub fn random_in_unit_disc() -> Vector3D<f32> {
 		}
 	}
 }
+
+/// Returns two unit vectors that together with `n` form an orthonormal basis.
+/// `n` must be normalized.
+// https://graphics.pixar.com/library/OrthonormalB/paper.pdf
+pub fn orthonormal_basis(n: Vector3D<f32>) -> (Vector3D<f32>, Vector3D<f32>) {
+	let sign = 1.0_f32.copysign(n.z);
+	let a = -1.0 / (sign + n.z);
+	let b = n.x * n.y * a;
+	(
+		Vector3D::new(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x),
+		Vector3D::new(b, sign + n.y * n.y * a, -n.y),
+	)
+}
+
+/// Reads an array with the given dimensions, after checking that the rest of
+/// the file is large enough to hold it. This way a corrupt header gives an
+/// error, instead of allocating a huge amount of memory.
+pub fn read_array<T, C: FromIterator<T>>(
+	reader: &mut BufReader<File>,
+	dimensions: &[u32],
+	read: fn(&mut BufReader<File>) -> io::Result<T>,
+) -> io::Result<C> {
+	let remaining = (reader.get_ref().metadata()?.len()).saturating_sub(reader.stream_position()?);
+	let count = dimensions
+		.iter()
+		.try_fold(1_usize, |count, &n| count.checked_mul(n as usize));
+	let size = count.and_then(|count| count.checked_mul(size_of::<T>()));
+	match (count, size) {
+		(Some(count), Some(size)) if size as u64 <= remaining => {
+			(0..count).map(|_| read(reader)).collect()
+		}
+		_ => Err(io::Error::new(
+			io::ErrorKind::InvalidData,
+			"the file is truncated or corrupt",
+		)),
+	}
+}
+
+pub fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+	let mut bytes = [0; 4];
+	reader.read_exact(&mut bytes)?;
+	Ok(u32::from_le_bytes(bytes))
+}
+
+pub fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+	let mut bytes = [0; 8];
+	reader.read_exact(&mut bytes)?;
+	Ok(u64::from_le_bytes(bytes))
+}
+
+pub fn read_f32(reader: &mut impl Read) -> io::Result<f32> {
+	let mut bytes = [0; 4];
+	reader.read_exact(&mut bytes)?;
+	Ok(f32::from_le_bytes(bytes))
+}
+
+/// Writes an optional region as a flag followed by its position and size.
+pub fn write_region(writer: &mut impl Write, region: Option<Region>) -> io::Result<()> {
+	let region = region.map_or([0; 5], |r| [1, r.x, r.y, r.width, r.height]);
+	for value in region {
+		writer.write_all(&value.to_le_bytes())?;
+	}
+	Ok(())
+}
+
+pub fn read_region(reader: &mut impl Read) -> io::Result<Option<Region>> {
+	let present = read_u32(reader)? != 0;
+	let x = read_u32(reader)?;
+	let y = read_u32(reader)?;
+	let width = read_u32(reader)?;
+	let height = read_u32(reader)?;
+	Ok(present.then(|| Region::new(x, y, width, height)))
+}
+
+/// Writes an optional scene hash as a flag followed by the hash.
+pub fn write_scene_hash(writer: &mut impl Write, scene_hash: Option<u64>) -> io::Result<()> {
+	writer.write_all(&(scene_hash.is_some() as u32).to_le_bytes())?;
+	writer.write_all(&scene_hash.unwrap_or(0).to_le_bytes())
+}
+
+pub fn read_scene_hash(reader: &mut impl Read) -> io::Result<Option<u64>> {
+	let present = read_u32(reader)? != 0;
+	let scene_hash = read_u64(reader)?;
+	Ok(present.then_some(scene_hash))
+}